@@ -0,0 +1,241 @@
+use bloom_filter::blocked::BlockedBloomFilter;
+use bloom_filter::ribbon::RibbonFilter;
+use bloom_filter::simple::SimpleBloomFilter;
+use bloom_filter::slatedb::{BloomFilterSize, SlatedbBloomFilter};
+use bloom_filter::xor::XorFilter;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const NUM_KEYS: usize = 1_000_000;
+const NUM_LOOKUPS: usize = 10_000;
+const BITS_PER_KEY: u32 = 10;
+/// Result bits of a ribbon filter spending about as much, ~9.9 bits per key.
+const RIBBON_RESULT_BITS: u32 = 9;
+/// Enough keys for the filter bits (~25MB) to spill out of the CPU cache.
+const NUM_KEYS_LARGE: usize = 20_000_000;
+
+fn keys(prefix: &str, n: usize) -> Vec<Vec<u8>> {
+    (0..n)
+        .map(|i| format!("{}-{}", prefix, i).into_bytes())
+        .collect()
+}
+
+fn measured_fpr(absent: &[Vec<u8>], has_key: impl Fn(&[u8]) -> bool) -> f64 {
+    let false_positives = absent.iter().filter(|key| has_key(key)).count();
+    false_positives as f64 / absent.len() as f64
+}
+
+/// Insert and query throughput of the two plain bloom filters.
+fn insert_query_benchmark(c: &mut Criterion) {
+    let size = BloomFilterSize::with_bits_per_key(NUM_LOOKUPS, BITS_PER_KEY);
+    let present = keys("key", NUM_LOOKUPS);
+    let absent = keys("absent", NUM_LOOKUPS);
+
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    group.bench_function("simple", |b| {
+        b.iter(|| {
+            let mut filter = SimpleBloomFilter::with_size(size);
+            for key in &present {
+                filter.add_key(black_box(key));
+            }
+            filter
+        })
+    });
+    group.bench_function("slatedb", |b| {
+        b.iter(|| {
+            let mut filter = SlatedbBloomFilter::with_size(size);
+            for key in &present {
+                filter.add_key(black_box(key));
+            }
+            filter
+        })
+    });
+    group.finish();
+
+    let mut simple = SimpleBloomFilter::with_size(size);
+    let mut slatedb = SlatedbBloomFilter::with_size(size);
+    for key in &present {
+        simple.add_key(key);
+        slatedb.add_key(key);
+    }
+    let mut group = c.benchmark_group("query");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    for (name, lookups) in [("present", &present), ("absent", &absent)] {
+        group.bench_function(format!("simple/{}", name), |b| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|key| simple.has_key(black_box(key)))
+                    .count()
+            })
+        });
+        group.bench_function(format!("slatedb/{}", name), |b| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|key| slatedb.has_key(black_box(key)))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn lookup_benchmark(c: &mut Criterion) {
+    let size = BloomFilterSize::with_bits_per_key(NUM_KEYS, BITS_PER_KEY);
+    let present = keys("key", NUM_KEYS);
+    let absent = keys("absent", NUM_LOOKUPS * 10);
+
+    let mut slatedb = SlatedbBloomFilter::with_size(size);
+    let mut blocked = BlockedBloomFilter::with_size(size);
+    for key in &present {
+        slatedb.add_key(key);
+        blocked.add_key(key);
+    }
+    println!(
+        "fpr at {} bits/key: theoretical {:.4}, slatedb {:.4}, blocked {:.4}",
+        BITS_PER_KEY,
+        size.false_positive_rate(),
+        measured_fpr(&absent, |key| slatedb.has_key(key)),
+        measured_fpr(&absent, |key| blocked.has_key(key)),
+    );
+
+    let mut group = c.benchmark_group("lookup");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    for (name, lookups) in [
+        ("present", &present[..NUM_LOOKUPS]),
+        ("absent", &absent[..NUM_LOOKUPS]),
+    ] {
+        group.bench_function(format!("slatedb/{}", name), |b| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|key| slatedb.has_key(black_box(key)))
+                    .count()
+            })
+        });
+        group.bench_function(format!("blocked/{}", name), |b| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|key| blocked.has_key(black_box(key)))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// `has_keys`/`add_keys` against one key at a time, on a filter in cache and one that is not.
+fn batch_benchmark(c: &mut Criterion) {
+    let lookups = keys("absent", NUM_LOOKUPS);
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    for (name, num_keys) in [("small", NUM_LOOKUPS), ("large", NUM_KEYS_LARGE)] {
+        let size = BloomFilterSize::with_bits_per_key(num_keys, BITS_PER_KEY);
+        let mut filter = SlatedbBloomFilter::with_size(size);
+        for i in 0..num_keys {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        group.bench_function(format!("{}/has_key", name), |b| {
+            b.iter(|| {
+                lookups
+                    .iter()
+                    .filter(|key| filter.has_key(black_box(key)))
+                    .count()
+            })
+        });
+        group.bench_function(format!("{}/has_keys", name), |b| {
+            b.iter(|| filter.has_keys(black_box(&lookups)))
+        });
+        group.bench_function(format!("{}/add_key", name), |b| {
+            b.iter(|| {
+                for key in &lookups {
+                    filter.add_key(black_box(key));
+                }
+            })
+        });
+        group.bench_function(format!("{}/add_keys", name), |b| {
+            b.iter(|| filter.add_keys(black_box(&lookups)))
+        });
+    }
+    group.finish();
+}
+
+/// Static filters against a bloom filter spending the same ~10 bits per key, lookups and builds.
+fn static_filter_benchmark(c: &mut Criterion) {
+    let size = BloomFilterSize::with_bits_per_key(NUM_KEYS, BITS_PER_KEY);
+    let present = keys("key", NUM_KEYS);
+    let absent = keys("absent", NUM_LOOKUPS * 10);
+
+    let mut slatedb = SlatedbBloomFilter::with_size(size);
+    for key in &present {
+        slatedb.add_key(key);
+    }
+    let xor = XorFilter::build(&present);
+    let ribbon = RibbonFilter::build(&present, RIBBON_RESULT_BITS);
+    let bits_per_key = |encoded_len: usize| (encoded_len * 8) as f64 / NUM_KEYS as f64;
+    println!(
+        "slatedb: {:.2} bits/key, fpr {:.4}; xor8: {:.2} bits/key, fpr {:.4}; ribbon: {:.2} bits/key, fpr {:.4}",
+        bits_per_key(slatedb.encode().len()),
+        measured_fpr(&absent, |key| slatedb.has_key(key)),
+        bits_per_key(xor.encode().len()),
+        measured_fpr(&absent, |key| xor.has_key(key)),
+        bits_per_key(ribbon.encode().len()),
+        measured_fpr(&absent, |key| ribbon.has_key(key)),
+    );
+
+    let mut group = c.benchmark_group("static");
+    group.throughput(Throughput::Elements(NUM_LOOKUPS as u64));
+    let lookups = &absent[..NUM_LOOKUPS];
+    group.bench_function("slatedb/absent", |b| {
+        b.iter(|| {
+            lookups
+                .iter()
+                .filter(|key| slatedb.has_key(black_box(key)))
+                .count()
+        })
+    });
+    group.bench_function("xor8/absent", |b| {
+        b.iter(|| {
+            lookups
+                .iter()
+                .filter(|key| xor.has_key(black_box(key)))
+                .count()
+        })
+    });
+    group.bench_function("ribbon/absent", |b| {
+        b.iter(|| {
+            lookups
+                .iter()
+                .filter(|key| ribbon.has_key(black_box(key)))
+                .count()
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(NUM_KEYS as u64));
+    group.bench_function("slatedb", |b| {
+        b.iter(|| {
+            let mut filter = SlatedbBloomFilter::with_size(size);
+            filter.add_keys(black_box(&present));
+            filter
+        })
+    });
+    group.bench_function("xor8", |b| b.iter(|| XorFilter::build(black_box(&present))));
+    group.bench_function("ribbon", |b| {
+        b.iter(|| RibbonFilter::build(black_box(&present), RIBBON_RESULT_BITS))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    insert_query_benchmark,
+    lookup_benchmark,
+    batch_benchmark,
+    static_filter_benchmark
+);
+criterion_main!(benches);
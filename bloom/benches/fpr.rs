@@ -0,0 +1,110 @@
+//! Observed against theoretical false positive rate across bits-per-key settings.
+//!
+//! Inserts `NUM_KEYS` random keys into each filter, probes `NUM_PROBES` random keys that were
+//! never inserted and prints one row per bits-per-key setting:
+//! ```text
+//! cargo bench -p bloom-filter --bench fpr
+//! ```
+use bloom_filter::blocked::BlockedBloomFilter;
+use bloom_filter::ribbon::RibbonFilter;
+use bloom_filter::simple::SimpleBloomFilter;
+use bloom_filter::slatedb::{BloomFilterSize, SlatedbBloomFilter};
+use bloom_filter::xor::XorFilter;
+
+const NUM_KEYS: usize = 100_000;
+const NUM_PROBES: usize = 1_000_000;
+const BITS_PER_KEY: [u32; 8] = [2, 4, 6, 8, 10, 12, 16, 20];
+const RIBBON_RESULT_BITS: [u32; 7] = [2, 4, 6, 7, 8, 10, 14];
+
+/// splitmix64, https://prng.di.unimi.it/splitmix64.c, a copy of the crate private
+/// `hash::splitmix64`.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// `n` random 16-byte keys whose first byte is `tag`, keys with different tags never collide.
+fn random_keys(rng: &mut Rng, tag: u8, n: usize) -> Vec<Vec<u8>> {
+    (0..n)
+        .map(|_| {
+            let mut key = Vec::with_capacity(16);
+            key.push(tag);
+            key.extend_from_slice(&rng.next_u64().to_le_bytes());
+            key.extend_from_slice(&rng.next_u64().to_le_bytes()[..7]);
+            key
+        })
+        .collect()
+}
+
+fn observed_fpr(absent: &[Vec<u8>], has_key: impl Fn(&[u8]) -> bool) -> f64 {
+    let false_positives = absent.iter().filter(|key| has_key(key)).count();
+    false_positives as f64 / absent.len() as f64
+}
+
+fn main() {
+    let mut rng = Rng(0x5eed);
+    let present = random_keys(&mut rng, 0, NUM_KEYS);
+    let absent = random_keys(&mut rng, 1, NUM_PROBES);
+    println!("{} keys, {} absent probes", present.len(), absent.len());
+    println!(
+        "{:>11} {:>6} {:>11} {:>10} {:>10} {:>10}",
+        "bits/key", "probes", "theoretical", "simple", "slatedb", "blocked"
+    );
+    for bits_per_key in BITS_PER_KEY {
+        let size = BloomFilterSize::with_bits_per_key(NUM_KEYS, bits_per_key);
+        let mut simple = SimpleBloomFilter::with_size(size);
+        let mut slatedb = SlatedbBloomFilter::with_size(size);
+        let mut blocked = BlockedBloomFilter::with_size(size);
+        for key in &present {
+            simple.add_key(key);
+            slatedb.add_key(key);
+            blocked.add_key(key);
+        }
+        for key in &present {
+            assert!(
+                slatedb.has_key(key),
+                "false negative at {} bits/key",
+                bits_per_key
+            );
+        }
+        println!(
+            "{:>11} {:>6} {:>11.6} {:>10.6} {:>10.6} {:>10.6}",
+            bits_per_key,
+            size.num_probes,
+            size.false_positive_rate(),
+            observed_fpr(&absent, |key| simple.has_key(key)),
+            observed_fpr(&absent, |key| slatedb.has_key(key)),
+            observed_fpr(&absent, |key| blocked.has_key(key)),
+        );
+    }
+
+    let xor = XorFilter::build(&present);
+    println!(
+        "xor8: {:.2} bits/key, theoretical {:.6}, observed {:.6}",
+        (xor.encode().len() * 8) as f64 / NUM_KEYS as f64,
+        1.0 / 256.0,
+        observed_fpr(&absent, |key| xor.has_key(key)),
+    );
+
+    println!(
+        "{:>11} {:>11} {:>11} {:>10}",
+        "result bits", "bits/key", "theoretical", "ribbon"
+    );
+    for result_bits in RIBBON_RESULT_BITS {
+        let ribbon = RibbonFilter::build(&present, result_bits);
+        println!(
+            "{:>11} {:>11.2} {:>11.6} {:>10.6}",
+            result_bits,
+            (ribbon.encode().len() * 8) as f64 / NUM_KEYS as f64,
+            ribbon.false_positive_rate(),
+            observed_fpr(&absent, |key| ribbon.has_key(key)),
+        );
+    }
+}
@@ -0,0 +1,59 @@
+#![no_main]
+
+use bloom_filter::blocked::BlockedBloomFilter;
+use bloom_filter::countmin::CountMinSketch;
+use bloom_filter::cuckoo::CuckooFilter;
+use bloom_filter::hyperloglog::HyperLogLog;
+use bloom_filter::ribbon::RibbonFilter;
+use bloom_filter::scalable::ScalableBloomFilter;
+use bloom_filter::slatedb::{BitEncoding, BloomFilterView, SlatedbBloomFilter};
+use bloom_filter::xor::XorFilter;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // decoding arbitrary bytes must return an error rather than panic, and whatever decodes
+    // must answer queries and encode back to the same bytes, unless it was an older version
+    // or golomb-rice coded, which can pick another rice parameter.
+    if let Ok(filter) = SlatedbBloomFilter::decode(data) {
+        filter.has_key(b"key");
+        if BloomFilterView::new(data).is_ok() && data[0] > 2 {
+            assert_eq!(filter.encode(), data);
+        }
+        let coded = filter.encode_with(BitEncoding::GolombRice);
+        assert_eq!(
+            SlatedbBloomFilter::decode(&coded).unwrap().encode(),
+            filter.encode()
+        );
+    }
+    if let Ok(view) = BloomFilterView::new(data) {
+        view.has_key(b"key");
+    }
+    if let Ok(filter) = BlockedBloomFilter::decode(data) {
+        filter.has_key(b"key");
+        assert_eq!(filter.encode(), data);
+    }
+    if let Ok(filter) = CuckooFilter::decode(data) {
+        filter.has_key(b"key");
+        assert_eq!(filter.encode(), data);
+    }
+    if let Ok(filter) = ScalableBloomFilter::decode(data) {
+        filter.has_key(b"key");
+        assert_eq!(filter.encode(), data);
+    }
+    if let Ok(filter) = RibbonFilter::decode(data) {
+        filter.has_key(b"key");
+        assert_eq!(filter.encode(), data);
+    }
+    if let Ok(filter) = XorFilter::decode(data) {
+        filter.has_key(b"key");
+        assert_eq!(filter.encode(), data);
+    }
+    if let Ok(sketch) = CountMinSketch::decode(data) {
+        sketch.estimate(b"key");
+        assert_eq!(sketch.encode(), data);
+    }
+    if let Ok(sketch) = HyperLogLog::decode(data) {
+        sketch.estimate();
+        assert_eq!(sketch.encode(), data);
+    }
+});
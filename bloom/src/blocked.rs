@@ -0,0 +1,264 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{ensure_consumed, ensure_remaining, verify_checksum, FilterDecodeError};
+use crate::filter::Filter;
+use crate::hash::{hash64, reduce, DEFAULT_SEED, HASH_XXH64};
+use crate::slatedb::{estimate_num_keys, BloomFilterSize};
+
+/// Number of bits in a block, one 64-byte cache line.
+const BLOCK_BITS: usize = 512;
+/// Version of the encoded filter layout, bumped on any incompatible change.
+const FORMAT_VERSION: u8 = 1;
+/// version, hash id, num_probes, seed, num_blocks
+const HEADER_LEN: usize = 1 + 1 + 4 + 8 + 8;
+
+/// One cache line worth of bits. The alignment keeps a block from straddling two lines.
+#[derive(Clone, Copy, Default)]
+#[repr(align(64))]
+struct Block([u64; BLOCK_BITS / 64]);
+
+/// Cache-line blocked bloom filter.
+///
+/// The upper half of the key hash picks a single 512-bit block and every probe lands in
+/// that block, so a lookup costs at most one cache miss instead of one per probe. Probe
+/// positions are derived from the lower half of the hash by repeated multiplication with the
+/// golden ratio, the same scheme as RocksDB's FastLocalBloom. Confining probes to a block
+/// costs a slightly higher false positive rate than spreading them over the whole filter.
+/// https://github.com/facebook/rocksdb/wiki/RocksDB-Bloom-Filter#new-bloom-filter-format
+pub struct BlockedBloomFilter {
+    blocks: Vec<Block>,
+    num_probes: u32,
+    seed: u64,
+}
+
+impl BlockedBloomFilter {
+    /// Create a filter with at least `size` bits, rounded up to a whole number of blocks.
+    pub fn new(size: usize, num_probes: u32) -> Self {
+        Self::new_with_seed(size, num_probes, DEFAULT_SEED)
+    }
+
+    pub fn new_with_seed(size: usize, num_probes: u32, seed: u64) -> Self {
+        let num_blocks = size.div_ceil(BLOCK_BITS).max(1);
+        Self {
+            blocks: vec![Block::default(); num_blocks],
+            num_probes,
+            seed,
+        }
+    }
+
+    /// Create a filter with bit count and probe count derived from `size`.
+    pub fn with_size(size: BloomFilterSize) -> Self {
+        Self::new(size.num_bits, size.num_probes)
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.blocks.len() * BLOCK_BITS
+    }
+
+    /// Number of bytes used by the filter, including the blocks.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.blocks.capacity() * std::mem::size_of::<Block>()
+    }
+
+    /// Decode a filter written by [`BlockedBloomFilter::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<BlockedBloomFilter, FilterDecodeError> {
+        let body = verify_checksum(encoded)?;
+        let mut buf = body;
+        ensure_remaining(buf, HEADER_LEN, 0)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(FilterDecodeError::UnknownVersion(version));
+        }
+        let hash_algorithm = buf.get_u8();
+        if hash_algorithm != HASH_XXH64 {
+            return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+        }
+        let num_probes = buf.get_u32();
+        let seed = buf.get_u64();
+        let num_blocks = usize::try_from(buf.get_u64())
+            .map_err(|_| FilterDecodeError::InvalidHeader("num_blocks does not fit in memory"))?;
+        if num_blocks == 0 {
+            return Err(FilterDecodeError::InvalidHeader("num_blocks is zero"));
+        }
+        ensure_remaining(buf, num_blocks.saturating_mul(BLOCK_BITS / 8), HEADER_LEN)?;
+        let blocks = (0..num_blocks)
+            .map(|_| {
+                let mut block = Block::default();
+                for word in block.0.iter_mut() {
+                    *word = buf.get_u64_le();
+                }
+                block
+            })
+            .collect();
+        ensure_consumed(buf, body)?;
+        Ok(Self {
+            blocks,
+            num_probes,
+            seed,
+        })
+    }
+
+    /// Encode the filter.
+    ///
+    /// Layout (big-endian header, little-endian words):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | num_probes (u32) | seed (u64) | num_blocks (u64) |
+    /// | blocks (num_blocks * 8 u64 words) | crc32 of everything before it (u32) |
+    /// ```
+    pub fn encode(&self) -> Bytes {
+        let mut encoded = BytesMut::with_capacity(HEADER_LEN + self.blocks.len() * 64 + 4);
+        encoded.put_u8(FORMAT_VERSION);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u32(self.num_probes);
+        encoded.put_u64(self.seed);
+        encoded.put_u64(self.blocks.len() as u64);
+        for block in &self.blocks {
+            for word in block.0 {
+                encoded.put_u64_le(word);
+            }
+        }
+        encoded.put_u32(crc32fast::hash(&encoded));
+        encoded.freeze()
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        let (block, mut h) = self.hash_key(key);
+        let block = &mut self.blocks[block];
+        for _ in 0..self.num_probes {
+            let bit = Self::bit_in_block(h);
+            block.0[bit / 64] |= 1 << (bit % 64);
+            h = h.wrapping_mul(0x9e3779b9);
+        }
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        let (block, mut h) = self.hash_key(key);
+        let block = &self.blocks[block];
+        for _ in 0..self.num_probes {
+            let bit = Self::bit_in_block(h);
+            if block.0[bit / 64] & (1 << (bit % 64)) == 0 {
+                return false;
+            }
+            h = h.wrapping_mul(0x9e3779b9);
+        }
+        true // if all bits are 1, key may be in set (false positive possible)
+    }
+
+    /// Return the block index of the key and the seed of its in-block probes.
+    fn hash_key(&self, key: &[u8]) -> (usize, u32) {
+        let hash = hash64(key, self.seed);
+        let block = reduce((hash >> 32) as u32, self.blocks.len());
+        (block, hash as u32)
+    }
+
+    /// The top 9 bits of the probe hash address one of the 512 bits of a block.
+    fn bit_in_block(h: u32) -> usize {
+        (h >> (32 - 9)) as usize
+    }
+}
+
+impl Filter for BlockedBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    fn estimated_len(&self) -> usize {
+        let set_bits: usize = self
+            .blocks
+            .iter()
+            .flat_map(|block| block.0)
+            .map(|word| word.count_ones() as usize)
+            .sum();
+        estimate_num_keys(self.num_bits(), set_bits, self.num_probes)
+    }
+
+    fn encode(&self) -> Bytes {
+        BlockedBloomFilter::encode(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_works() {
+        let filter_size = 1000;
+        let num_probes = 3;
+        let mut filter = BlockedBloomFilter::new(filter_size, num_probes);
+        filter.add_key(("apple").as_bytes());
+        filter.add_key(("banana").as_bytes());
+        filter.add_key(("orange").as_bytes());
+
+        assert!(filter.has_key("apple".as_bytes()));
+        assert!(!filter.has_key("grape".as_bytes()));
+        assert!(!filter.has_key("mango".as_bytes()));
+        assert!(!filter.has_key("kiwi".as_bytes()));
+    }
+
+    #[test]
+    fn test_size_is_rounded_to_blocks() {
+        let filter = BlockedBloomFilter::new(1000, 3);
+        assert_eq!(filter.num_bits(), 1024);
+        assert_eq!(std::mem::align_of::<Block>(), 64);
+        assert_eq!(std::mem::size_of::<Block>(), 64);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut filter = BlockedBloomFilter::new_with_seed(2000, 5, 3);
+        for i in 0..200 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let encoded = filter.encode();
+        assert_eq!(encoded.len(), HEADER_LEN + 4 * 64 + 4);
+
+        let decoded = BlockedBloomFilter::decode(&encoded).unwrap();
+        for i in 0..200 {
+            assert!(decoded.has_key(format!("key-{}", i).as_bytes()));
+        }
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn test_decode_rejects_damaged_input() {
+        let encoded = BlockedBloomFilter::new_with_seed(2000, 5, 3).encode();
+        for len in 0..encoded.len() {
+            assert!(BlockedBloomFilter::decode(&encoded[..len]).is_err());
+        }
+        let mut longer = encoded.to_vec();
+        longer.push(0);
+        assert!(BlockedBloomFilter::decode(&longer).is_err());
+
+        // a well formed checksum around zero blocks.
+        let mut empty = encoded[..HEADER_LEN].to_vec();
+        empty[14..22].copy_from_slice(&0u64.to_be_bytes());
+        empty.extend_from_slice(&crc32fast::hash(&empty).to_be_bytes());
+        assert_eq!(
+            BlockedBloomFilter::decode(&empty).err(),
+            Some(FilterDecodeError::InvalidHeader("num_blocks is zero"))
+        );
+    }
+
+    #[test]
+    fn test_false_positive_rate_close_to_theory() {
+        let size = BloomFilterSize::with_bits_per_key(10_000, 10);
+        let mut filter = BlockedBloomFilter::with_size(size);
+        for i in 0..10_000 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        for i in 0..10_000 {
+            assert!(filter.has_key(format!("key-{}", i).as_bytes()));
+        }
+        let false_positives = (0..100_000)
+            .filter(|i| filter.has_key(format!("absent-{}", i).as_bytes()))
+            .count();
+        // theory is ~0.84% for an unblocked filter, blocking adds a little on top.
+        let fpr = false_positives as f64 / 100_000.0;
+        assert!(fpr < 0.015, "fpr {}", fpr);
+    }
+}
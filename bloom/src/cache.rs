@@ -0,0 +1,392 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::slatedb::SlatedbBloomFilter;
+
+/// Counters of what `get` did, and what the cache holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from memory.
+    pub hits: u64,
+    /// Lookups that called the loader.
+    pub misses: u64,
+    /// Filters dropped to stay under the memory cap.
+    pub evictions: u64,
+    pub entries: usize,
+    /// Memory used by the cached filters, see [`SlatedbBloomFilter::memory_size`].
+    pub size_bytes: usize,
+}
+
+/// Cache of decoded filters keyed by file id, for stores with more sorted runs than filters
+/// that fit in memory.
+///
+/// A miss calls the loader for the encoded filter bytes of the file and decodes them. The
+/// least recently used filters are evicted once the decoded filters take more than
+/// `capacity_bytes`. Filters are handed out as `Arc`, so one that is evicted while a reader
+/// still holds it stays alive until the reader is done.
+///
+/// The loader runs without the cache lock held, two threads missing on the same file at once
+/// both load it and the first one to finish is kept. A load that a `remove` of its file
+/// overlaps returns the filter it loaded without caching it.
+pub struct FilterCache<L> {
+    loader: L,
+    capacity_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+struct Inner {
+    entries: HashMap<u64, Entry>,
+    /// File ids by last use, oldest first.
+    lru: BTreeMap<u64, u64>,
+    /// Incremented on every use, orders `lru`.
+    clock: u64,
+    size_bytes: usize,
+    /// Loads in flight by file id.
+    loads: HashMap<u64, Load>,
+}
+
+struct Load {
+    in_flight: usize,
+    /// Incremented by every `remove` of the file, a load only caches its filter if this did
+    /// not change while it ran.
+    generation: u64,
+}
+
+struct Entry {
+    filter: Arc<SlatedbBloomFilter>,
+    size_bytes: usize,
+    last_used: u64,
+}
+
+impl<L> FilterCache<L>
+where
+    L: Fn(u64) -> io::Result<Vec<u8>>,
+{
+    /// `loader` returns the encoded filter of a file, as written by
+    /// [`SlatedbBloomFilter::encode`].
+    pub fn new(capacity_bytes: usize, loader: L) -> Self {
+        Self {
+            loader,
+            capacity_bytes,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                size_bytes: 0,
+                loads: HashMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Filter of `file_id`, loaded and decoded on a miss.
+    ///
+    /// Loader errors are returned as is and bytes that do not decode as `InvalidData`,
+    /// neither is cached so the next call tries again. A filter larger than the whole cache
+    /// is returned without being cached.
+    pub fn get(&self, file_id: u64) -> io::Result<Arc<SlatedbBloomFilter>> {
+        let generation = {
+            let mut inner = self.lock();
+            if let Some(filter) = inner.touch(file_id) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(filter);
+            }
+            inner.start_load(file_id)
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let loaded = self.load(file_id);
+
+        let mut inner = self.lock();
+        let removed = inner.finish_load(file_id) != generation;
+        let filter = loaded?;
+        let size_bytes = filter.memory_size();
+        if removed || size_bytes > self.capacity_bytes {
+            return Ok(filter);
+        }
+        if let Some(cached) = inner.touch(file_id) {
+            // another thread loaded it meanwhile.
+            return Ok(cached);
+        }
+        while inner.size_bytes + size_bytes > self.capacity_bytes {
+            inner.evict_oldest();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        inner.insert(file_id, filter.clone(), size_bytes);
+        Ok(filter)
+    }
+
+    fn load(&self, file_id: u64) -> io::Result<Arc<SlatedbBloomFilter>> {
+        let encoded = (self.loader)(file_id)?;
+        let filter = SlatedbBloomFilter::decode(&encoded).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("filter of file {}: {}", file_id, err),
+            )
+        })?;
+        Ok(Arc::new(filter))
+    }
+
+    /// See [`SlatedbBloomFilter::has_key`], for the filter of `file_id`.
+    pub fn has_key(&self, file_id: u64, key: &[u8]) -> io::Result<bool> {
+        Ok(self.get(file_id)?.has_key(key))
+    }
+
+    /// Drop the filter of `file_id`, e.g. once the file is deleted by a compaction. Loads of
+    /// the file already in flight do not cache what they load.
+    pub fn remove(&self, file_id: u64) {
+        self.lock().remove(file_id);
+    }
+
+    pub fn capacity_bytes(&self) -> usize {
+        self.capacity_bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.lock();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            size_bytes: inner.size_bytes,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // the cached state stays consistent even if a holder panicked.
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Inner {
+    /// Cached filter of `file_id`, marked as the most recently used.
+    fn touch(&mut self, file_id: u64) -> Option<Arc<SlatedbBloomFilter>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(&file_id)?;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.clock, file_id);
+        entry.last_used = self.clock;
+        Some(entry.filter.clone())
+    }
+
+    fn insert(&mut self, file_id: u64, filter: Arc<SlatedbBloomFilter>, size_bytes: usize) {
+        self.clock += 1;
+        self.lru.insert(self.clock, file_id);
+        self.size_bytes += size_bytes;
+        self.entries.insert(
+            file_id,
+            Entry {
+                filter,
+                size_bytes,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn remove(&mut self, file_id: u64) {
+        if let Some(entry) = self.entries.remove(&file_id) {
+            self.lru.remove(&entry.last_used);
+            self.size_bytes -= entry.size_bytes;
+        }
+        if let Some(load) = self.loads.get_mut(&file_id) {
+            load.generation += 1;
+        }
+    }
+
+    /// Register a load of `file_id`, returns the generation to pass to `finish_load`.
+    fn start_load(&mut self, file_id: u64) -> u64 {
+        let load = self.loads.entry(file_id).or_insert(Load {
+            in_flight: 0,
+            generation: 0,
+        });
+        load.in_flight += 1;
+        load.generation
+    }
+
+    /// Unregister a load of `file_id`, returns the generation of the file, which differs from
+    /// the one `start_load` returned if the file was removed meanwhile.
+    fn finish_load(&mut self, file_id: u64) -> u64 {
+        let load = self.loads.get_mut(&file_id).expect("load was started");
+        load.in_flight -= 1;
+        let generation = load.generation;
+        if load.in_flight == 0 {
+            self.loads.remove(&file_id);
+        }
+        generation
+    }
+
+    fn evict_oldest(&mut self) {
+        let (_, file_id) = self
+            .lru
+            .pop_first()
+            .expect("an over-full cache is not empty");
+        let entry = self
+            .entries
+            .remove(&file_id)
+            .expect("lru entries are cached");
+        self.size_bytes -= entry.size_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Encoded filter of file `file_id`, holding the key `file-<id>`.
+    fn encoded_filter(file_id: u64) -> Vec<u8> {
+        let mut filter = SlatedbBloomFilter::new(1000, 4);
+        filter.add_key(format!("file-{}", file_id).as_bytes());
+        filter.encode().to_vec()
+    }
+
+    fn filter_size() -> usize {
+        SlatedbBloomFilter::decode(&encoded_filter(0))
+            .unwrap()
+            .memory_size()
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let loads = RefCell::new(Vec::new());
+        let cache = FilterCache::new(10 * filter_size(), |file_id| {
+            loads.borrow_mut().push(file_id);
+            Ok(encoded_filter(file_id))
+        });
+        assert!(cache.has_key(1, b"file-1").unwrap());
+        assert!(!cache.has_key(1, b"file-2").unwrap());
+        assert!(cache.has_key(2, b"file-2").unwrap());
+        assert_eq!(*loads.borrow(), vec![1, 2]);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                evictions: 0,
+                entries: 2,
+                size_bytes: 2 * filter_size(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let loads = RefCell::new(Vec::new());
+        let cache = FilterCache::new(3 * filter_size(), |file_id| {
+            loads.borrow_mut().push(file_id);
+            Ok(encoded_filter(file_id))
+        });
+        for file_id in [1, 2, 3, 1, 4] {
+            cache.get(file_id).unwrap();
+        }
+        // 2 was used longest ago when 4 needed room.
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.stats().size_bytes <= cache.capacity_bytes());
+        loads.borrow_mut().clear();
+        for file_id in [1, 3, 4, 2] {
+            cache.get(file_id).unwrap();
+        }
+        assert_eq!(*loads.borrow(), vec![2]);
+    }
+
+    #[test]
+    fn test_evicted_filter_stays_usable() {
+        let cache = FilterCache::new(filter_size(), |file_id| Ok(encoded_filter(file_id)));
+        let filter = cache.get(1).unwrap();
+        cache.get(2).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+        assert!(filter.has_key(b"file-1"));
+    }
+
+    #[test]
+    fn test_oversized_filter_is_not_cached() {
+        let cache = FilterCache::new(filter_size() - 1, |file_id| Ok(encoded_filter(file_id)));
+        assert!(cache.has_key(1, b"file-1").unwrap());
+        assert!(cache.has_key(1, b"file-1").unwrap());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (0, 2, 0));
+    }
+
+    #[test]
+    fn test_errors_are_not_cached() {
+        let cache = FilterCache::new(10 * filter_size(), |file_id| match file_id {
+            1 => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+            2 => Ok(b"not a filter".to_vec()),
+            _ => Ok(encoded_filter(file_id)),
+        });
+        assert_eq!(cache.get(1).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(cache.get(2).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(cache.get(2).is_err());
+        assert_eq!(cache.stats().misses, 3);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_remove() {
+        let cache = FilterCache::new(10 * filter_size(), |file_id| Ok(encoded_filter(file_id)));
+        cache.get(1).unwrap();
+        cache.remove(1);
+        cache.remove(7);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().size_bytes, 0);
+        cache.get(1).unwrap();
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn test_remove_during_load_is_not_undone() {
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (resume_tx, resume_rx) = std::sync::mpsc::channel::<()>();
+        let resume_rx = Mutex::new(resume_rx);
+        let cache = FilterCache::new(10 * filter_size(), |file_id| {
+            started_tx.send(()).unwrap();
+            resume_rx.lock().unwrap().recv().unwrap();
+            Ok(encoded_filter(file_id))
+        });
+        std::thread::scope(|scope| {
+            let load = scope.spawn(|| cache.get(1).unwrap());
+            started_rx.recv().unwrap();
+            cache.remove(1);
+            resume_tx.send(()).unwrap();
+            // the filter is still handed out, but not cached after the remove.
+            assert!(load.join().unwrap().has_key(b"file-1"));
+        });
+        assert_eq!(cache.stats().entries, 0);
+        assert!(cache.lock().loads.is_empty());
+
+        // later loads cache again.
+        resume_tx.send(()).unwrap();
+        cache.get(1).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let cache = FilterCache::new(4 * filter_size(), |file_id| Ok(encoded_filter(file_id)));
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        let file_id = (thread + i) % 8;
+                        let key = format!("file-{}", file_id);
+                        assert!(cache.has_key(file_id, key.as_bytes()).unwrap());
+                    }
+                });
+            }
+        });
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 4000);
+        assert!(stats.entries <= 4);
+        assert_eq!(stats.size_bytes, stats.entries * filter_size());
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use crate::filter::Filter;
+use crate::hash::{probes, DEFAULT_SEED};
+use crate::slatedb::{estimate_num_keys, BloomFilterSize, SlatedbBloomFilter};
+
+/// Bloom filter that many threads can add keys to and query at the same time.
+///
+/// Bits live in `AtomicU64` words and `add_key` sets them with `fetch_or`, so concurrent adds
+/// never lose each other's bits and no lock is needed. Bits are only ever set, so `Relaxed`
+/// ordering is enough: a key is guaranteed to be visible to a reader once the `add_key` call
+/// that added it happens-before the read, e.g. after joining the writer thread.
+pub struct ConcurrentBloomFilter {
+    /// Bit `i` lives in word `i / 64` at position `i % 64`.
+    words: Vec<AtomicU64>,
+    num_bits: usize,
+    num_probes: u32,
+    seed: u64,
+}
+
+impl ConcurrentBloomFilter {
+    pub fn new(size: usize, num_probes: u32) -> Self {
+        Self::new_with_seed(size, num_probes, DEFAULT_SEED)
+    }
+
+    /// Panics on a size of zero.
+    pub fn new_with_seed(size: usize, num_probes: u32, seed: u64) -> Self {
+        assert!(size > 0, "size must be positive");
+        Self {
+            words: (0..size.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            num_bits: size,
+            num_probes,
+            seed,
+        }
+    }
+
+    /// Create a filter with bit count and probe count derived from `size`.
+    pub fn with_size(size: BloomFilterSize) -> Self {
+        Self::new(size.num_bits, size.num_probes)
+    }
+
+    pub fn add_key(&self, key: &[u8]) {
+        for index in self.hash_key(key) {
+            self.words[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+        }
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        let indices = self.hash_key(key);
+        for index in indices {
+            if self.words[index / 64].load(Ordering::Relaxed) & (1 << (index % 64)) == 0 {
+                return false;
+            }
+        }
+        true // if all bits are 1, key may be in set (false positive possible)
+    }
+
+    /// Positions probed for `key`, computed lazily so a lookup can stop at the first unset one.
+    pub fn hash_key(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        probes(key, self.seed, self.num_probes, self.num_bits)
+    }
+
+    /// Snapshot the bits into a `SlatedbBloomFilter` that answers `has_key` identically and
+    /// can be encoded. Keys added concurrently with the snapshot may or may not be included.
+    pub fn freeze(&self) -> SlatedbBloomFilter {
+        // little-endian words put bit i in byte i / 8 at position i % 8, the layout of set_bit.
+        let mut buffer: Vec<u8> = self
+            .words
+            .iter()
+            .flat_map(|word| word.load(Ordering::Relaxed).to_le_bytes())
+            .collect();
+        buffer.truncate(self.num_bits.div_ceil(8));
+        SlatedbBloomFilter::from_buffer(buffer, self.num_bits, self.num_probes, self.seed)
+    }
+}
+
+impl Filter for ConcurrentBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    fn estimated_len(&self) -> usize {
+        let set_bits: usize = self
+            .words
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum();
+        estimate_num_keys(self.num_bits, set_bits, self.num_probes)
+    }
+
+    fn encode(&self) -> Bytes {
+        self.freeze().encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_works() {
+        let filter = ConcurrentBloomFilter::new(1000, 3);
+        filter.add_key(b"apple");
+        filter.add_key(b"banana");
+        filter.add_key(b"orange");
+
+        assert!(filter.has_key(b"apple"));
+        assert!(!filter.has_key(b"grape"));
+        assert!(!filter.has_key(b"mango"));
+        assert!(!filter.has_key(b"kiwi"));
+    }
+
+    #[test]
+    fn test_freeze_matches_sequential_filter() {
+        // 1003 bits leaves the last word and the last byte partially used.
+        let filter = ConcurrentBloomFilter::new_with_seed(1003, 4, 11);
+        let mut sequential = SlatedbBloomFilter::new_with_seed(1003, 4, 11);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            filter.add_key(key.as_bytes());
+            sequential.add_key(key.as_bytes());
+        }
+        assert_eq!(filter.freeze().encode(), sequential.encode());
+    }
+
+    #[test]
+    fn test_concurrent_adds_lose_no_bits() {
+        const NUM_THREADS: usize = 8;
+        const KEYS_PER_THREAD: usize = 20_000;
+        let size = BloomFilterSize::with_bits_per_key(NUM_THREADS * KEYS_PER_THREAD, 10);
+        let filter = Arc::new(ConcurrentBloomFilter::with_size(size));
+
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|t| {
+                let filter = Arc::clone(&filter);
+                thread::spawn(move || {
+                    for i in 0..KEYS_PER_THREAD {
+                        let key = format!("key-{}-{}", t, i);
+                        filter.add_key(key.as_bytes());
+                        // a thread always sees its own writes.
+                        assert!(filter.has_key(key.as_bytes()));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // every bit set by any thread must be there, so the result is bit for bit the
+        // filter a single thread would have built.
+        let mut sequential = SlatedbBloomFilter::with_size(size);
+        for t in 0..NUM_THREADS {
+            for i in 0..KEYS_PER_THREAD {
+                sequential.add_key(format!("key-{}-{}", t, i).as_bytes());
+            }
+        }
+        assert_eq!(filter.freeze().encode(), sequential.encode());
+    }
+}
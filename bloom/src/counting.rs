@@ -0,0 +1,228 @@
+use bytes::Bytes;
+
+use crate::filter::Filter;
+use crate::hash::{probes, DEFAULT_SEED};
+use crate::nibbles::{Nibbles, MAX_NIBBLE};
+use crate::slatedb::SlatedbBloomFilter;
+
+/// Largest value a 4-bit counter can hold.
+const MAX_COUNT: u8 = MAX_NIBBLE;
+
+/// Counting bloom filter, a bloom filter that supports removing keys.
+///
+/// Every slot holds a 4-bit counter instead of a single bit. Adding a key increments the
+/// counters of its probes and removing it decrements them. A counter that reaches
+/// `MAX_COUNT` saturates and stays there: it can no longer tell how many keys share the
+/// slot, so decrementing it could produce a false negative for another key.
+/// https://en.wikipedia.org/wiki/Counting_Bloom_filter
+pub struct CountingBloomFilter {
+    counters: Nibbles,
+    num_probes: u32,
+    seed: u64,
+}
+
+impl CountingBloomFilter {
+    pub fn new(size: usize, num_probes: u32) -> Self {
+        Self::new_with_seed(size, num_probes, DEFAULT_SEED)
+    }
+
+    /// Panics on a size of zero.
+    pub fn new_with_seed(size: usize, num_probes: u32, seed: u64) -> Self {
+        assert!(size > 0, "size must be positive");
+        Self {
+            counters: Nibbles::new(size),
+            num_probes,
+            seed,
+        }
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        for index in self.hash_key(key) {
+            let count = self.counters.get(index);
+            if count < MAX_COUNT {
+                self.counters.set(index, count + 1);
+            }
+        }
+    }
+
+    /// Remove a key previously added with `add_key`.
+    ///
+    /// Returns false and leaves the filter untouched if the key is definitely absent.
+    /// Removing a key that was never added but happens to be a false positive decrements
+    /// counters owned by other keys, so callers must only remove keys they inserted.
+    ///
+    /// Probes of a key can repeat an index, which `add_key` then increments once per probe.
+    /// A counter below the number of times the key probes it means the key was never added.
+    pub fn remove_key(&mut self, key: &[u8]) -> bool {
+        let mut indices: Vec<usize> = self.hash_key(key).collect();
+        indices.sort_unstable();
+        let enough = indices.chunk_by(|a, b| a == b).all(|run| {
+            let count = self.counters.get(run[0]);
+            count == MAX_COUNT || count as usize >= run.len()
+        });
+        if !enough {
+            return false;
+        }
+        for index in indices {
+            let count = self.counters.get(index);
+            if count < MAX_COUNT {
+                self.counters.set(index, count - 1);
+            }
+        }
+        true
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        let indices = self.hash_key(key);
+        for index in indices {
+            if self.counters.get(index) == 0 {
+                return false;
+            }
+        }
+        true // if all counters are non-zero, key may be in set (false positive possible)
+    }
+
+    /// Positions probed for `key`, computed lazily so a lookup can stop at the first unset one.
+    pub fn hash_key(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        probes(key, self.seed, self.num_probes, self.counters.len())
+    }
+
+    /// Number of counters that overflowed and can no longer be decremented.
+    pub fn saturated_counters(&self) -> usize {
+        self.counters
+            .iter()
+            .filter(|count| *count == MAX_COUNT)
+            .count()
+    }
+
+    /// Convert to a plain bloom filter holding the same keys, e.g. for `encode`.
+    ///
+    /// Both filters probe the same positions, so a key answers `has_key` identically.
+    pub fn to_bloom_filter(&self) -> SlatedbBloomFilter {
+        self.counters.to_bloom_filter(self.num_probes, self.seed)
+    }
+}
+
+impl Filter for CountingBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    /// Every add increments `num_probes` counters, so the counter total divided by the
+    /// number of probes is the number of adds, less whatever saturated counters dropped.
+    fn estimated_len(&self) -> usize {
+        let total: usize = self.counters.iter().map(|count| count as usize).sum();
+        total / self.num_probes.max(1) as usize
+    }
+
+    /// Encode as a plain bloom filter, counters can not be recovered from it.
+    fn encode(&self) -> Bytes {
+        self.to_bloom_filter().encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove_key() {
+        let mut filter = CountingBloomFilter::new(1000, 3);
+        filter.add_key(b"apple");
+        filter.add_key(b"banana");
+        assert!(filter.has_key(b"apple"));
+        assert!(filter.has_key(b"banana"));
+
+        assert!(filter.remove_key(b"apple"));
+        assert!(!filter.has_key(b"apple"));
+        assert!(filter.has_key(b"banana"));
+    }
+
+    #[test]
+    fn test_remove_absent_key_is_noop() {
+        let mut filter = CountingBloomFilter::new(1000, 3);
+        filter.add_key(b"apple");
+        assert!(!filter.remove_key(b"grape"));
+        assert!(filter.has_key(b"apple"));
+    }
+
+    #[test]
+    fn test_duplicate_adds_need_matching_removes() {
+        let mut filter = CountingBloomFilter::new(1000, 3);
+        filter.add_key(b"apple");
+        filter.add_key(b"apple");
+        assert!(filter.remove_key(b"apple"));
+        assert!(filter.has_key(b"apple"));
+        assert!(filter.remove_key(b"apple"));
+        assert!(!filter.has_key(b"apple"));
+    }
+
+    /// Key whose probes hit the same slot more than once in `filter`.
+    fn colliding_key(filter: &CountingBloomFilter) -> Vec<u8> {
+        (0..)
+            .map(|i| format!("key-{}", i).into_bytes())
+            .find(|key| {
+                let mut indices: Vec<usize> = filter.hash_key(key).collect();
+                indices.sort_unstable();
+                indices.windows(2).any(|pair| pair[0] == pair[1])
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_remove_key_with_repeated_probes() {
+        let mut filter = CountingBloomFilter::new(16, 4);
+        let key = colliding_key(&filter);
+        filter.add_key(&key);
+        assert!(filter.remove_key(&key));
+        assert!((0..16).all(|index| filter.counters.get(index) == 0));
+
+        // a false positive whose repeated slot holds fewer counts than it probes.
+        for index in filter.hash_key(&key).collect::<Vec<_>>() {
+            filter.counters.set(index, 1);
+        }
+        assert!(filter.has_key(&key));
+        assert!(!filter.remove_key(&key));
+        assert!(filter
+            .hash_key(&key)
+            .all(|index| filter.counters.get(index) == 1));
+    }
+
+    #[test]
+    fn test_saturated_counters_are_sticky() {
+        let mut filter = CountingBloomFilter::new(1000, 3);
+        for _ in 0..20 {
+            filter.add_key(b"apple");
+        }
+        assert_eq!(filter.saturated_counters(), 3);
+        for _ in 0..20 {
+            filter.remove_key(b"apple");
+        }
+        // The counters can no longer be trusted, so the key stays present.
+        assert!(filter.has_key(b"apple"));
+        assert_eq!(filter.saturated_counters(), 3);
+    }
+
+    #[test]
+    fn test_to_bloom_filter_matches_plain_filter() {
+        let mut counting = CountingBloomFilter::new_with_seed(1001, 4, 9);
+        let mut plain = SlatedbBloomFilter::new_with_seed(1001, 4, 9);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            counting.add_key(key.as_bytes());
+            plain.add_key(key.as_bytes());
+        }
+        counting.add_key(b"removed");
+        counting.remove_key(b"removed");
+
+        let converted = counting.to_bloom_filter();
+        assert_eq!(converted.encode(), plain.encode());
+        for i in 0..100 {
+            assert!(converted.has_key(format!("key-{}", i).as_bytes()));
+        }
+    }
+}
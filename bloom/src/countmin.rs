@@ -0,0 +1,310 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{
+    ensure_consumed, ensure_remaining, verify_checksum, FilterDecodeError, IncompatibleFilterError,
+};
+use crate::hash::{hash64, probes_from_hash, DEFAULT_SEED, HASH_XXH64};
+
+/// Version of the encoded sketch layout, bumped on any incompatible change.
+const FORMAT_VERSION: u8 = 1;
+/// version, hash id, depth, seed, width, total
+const HEADER_LEN: usize = 1 + 1 + 4 + 8 + 8 + 8;
+
+/// Count-min sketch, approximate frequency of every key in a stream in fixed memory.
+///
+/// `depth` rows of `width` counters, a key adds to one counter per row, picked with the same
+/// double hashing as the bloom filters. Other keys land on the same counters, so every counter
+/// over-counts and the estimate is the smallest of a key's counters: never below the true
+/// count, and above it by at most `e / width` of the total count with probability
+/// `1 - e^-depth`.
+///
+/// Adds use the conservative update: only counters that would drop below the new estimate
+/// are raised, which keeps the over-counting much lower on skewed streams.
+/// http://dimacs.rutgers.edu/~graham/pubs/papers/cm-full.pdf
+#[derive(Clone)]
+pub struct CountMinSketch {
+    /// Row `r` is `counters[r * width..(r + 1) * width]`.
+    counters: Vec<u64>,
+    width: usize,
+    depth: u32,
+    seed: u64,
+    /// Sum of all counts added.
+    total: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: u32) -> Self {
+        Self::new_with_seed(width, depth, DEFAULT_SEED)
+    }
+
+    pub fn new_with_seed(width: usize, depth: u32, seed: u64) -> Self {
+        assert!(width > 0 && depth > 0, "width and depth must be positive");
+        Self {
+            counters: vec![0; width * depth as usize],
+            width,
+            depth,
+            seed,
+            total: 0,
+        }
+    }
+
+    /// Size a sketch whose estimates exceed the true count by at most `epsilon * total` with
+    /// probability `1 - delta`.
+    pub fn with_error(epsilon: f64, delta: f64) -> Self {
+        assert!(epsilon > 0.0, "epsilon must be positive, got {}", epsilon);
+        assert!(
+            delta > 0.0 && delta < 1.0,
+            "delta must be in (0, 1), got {}",
+            delta
+        );
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as u32;
+        Self::new(width, depth)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Sum of all counts added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of bytes used by the sketch, including the counters.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.counters.capacity() * std::mem::size_of::<u64>()
+    }
+
+    pub fn increment(&mut self, key: &[u8]) {
+        self.add(key, 1)
+    }
+
+    /// Count `key` `count` more times.
+    pub fn add(&mut self, key: &[u8], count: u64) {
+        let hash = hash64(key, self.seed);
+        let estimate = self.cells(hash).map(|cell| self.counters[cell]).min();
+        let target = estimate.unwrap_or(0).saturating_add(count);
+        for cell in self.cells(hash) {
+            // conservative update: a counter already above the target was raised by other
+            // keys and does not need to move.
+            self.counters[cell] = self.counters[cell].max(target);
+        }
+        self.total = self.total.saturating_add(count);
+    }
+
+    /// Estimated count of `key`, never below the true count.
+    pub fn estimate(&self, key: &[u8]) -> u64 {
+        self.cells(hash64(key, self.seed))
+            .map(|cell| self.counters[cell])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Add the counts of `other`, as if its stream had been added to this sketch too.
+    ///
+    /// Counters are summed, so estimates stay upper bounds, but they are looser than
+    /// conservatively updating one sketch with both streams would have been.
+    pub fn merge(&mut self, other: &CountMinSketch) -> Result<(), IncompatibleFilterError> {
+        if self.width != other.width {
+            return Err(IncompatibleFilterError::Width {
+                left: self.width,
+                right: other.width,
+            });
+        }
+        if self.depth != other.depth {
+            return Err(IncompatibleFilterError::Depth {
+                left: self.depth,
+                right: other.depth,
+            });
+        }
+        if self.seed != other.seed {
+            return Err(IncompatibleFilterError::Seed {
+                left: self.seed,
+                right: other.seed,
+            });
+        }
+        for (counter, other) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(*other);
+        }
+        self.total = self.total.saturating_add(other.total);
+        Ok(())
+    }
+
+    /// Decode a sketch written by [`CountMinSketch::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<CountMinSketch, FilterDecodeError> {
+        let body = verify_checksum(encoded)?;
+        let mut buf = body;
+        ensure_remaining(buf, HEADER_LEN, 0)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(FilterDecodeError::UnknownVersion(version));
+        }
+        let hash_algorithm = buf.get_u8();
+        if hash_algorithm != HASH_XXH64 {
+            return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+        }
+        let depth = buf.get_u32();
+        let seed = buf.get_u64();
+        let width = usize::try_from(buf.get_u64())
+            .map_err(|_| FilterDecodeError::InvalidHeader("width does not fit in memory"))?;
+        let total = buf.get_u64();
+        if width == 0 || depth == 0 {
+            return Err(FilterDecodeError::InvalidHeader("width or depth is zero"));
+        }
+        let num_counters = width
+            .checked_mul(depth as usize)
+            .ok_or(FilterDecodeError::InvalidHeader("too many counters"))?;
+        ensure_remaining(buf, num_counters.saturating_mul(8), body.len() - buf.len())?;
+        let counters = (0..num_counters).map(|_| buf.get_u64()).collect();
+        ensure_consumed(buf, body)?;
+        Ok(Self {
+            counters,
+            width,
+            depth,
+            seed,
+            total,
+        })
+    }
+
+    /// Encode the sketch.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | depth (u32) | seed (u64) | width (u64) |
+    /// | total (u64) | counters (depth * width u64, row by row) | crc32 of everything before it (u32) |
+    /// ```
+    pub fn encode(&self) -> Bytes {
+        let mut encoded = BytesMut::with_capacity(HEADER_LEN + self.counters.len() * 8 + 4);
+        encoded.put_u8(FORMAT_VERSION);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u32(self.depth);
+        encoded.put_u64(self.seed);
+        encoded.put_u64(self.width as u64);
+        encoded.put_u64(self.total);
+        for counter in &self.counters {
+            encoded.put_u64(*counter);
+        }
+        encoded.put_u32(crc32fast::hash(&encoded));
+        encoded.freeze()
+    }
+
+    /// Index into `counters` of the counter in each row of the key with `hash`.
+    fn cells(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let width = self.width;
+        probes_from_hash(hash, self.depth, width)
+            .enumerate()
+            .map(move |(row, column)| row * width + column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_are_never_underestimated() {
+        let mut sketch = CountMinSketch::new(200, 4);
+        // zipf-like stream: key i appears 1000 / (i + 1) times.
+        for i in 0..1000u64 {
+            sketch.add(format!("key-{}", i).as_bytes(), 1000 / (i + 1));
+        }
+        for i in 0..1000u64 {
+            let estimate = sketch.estimate(format!("key-{}", i).as_bytes());
+            assert!(estimate >= 1000 / (i + 1));
+        }
+        // heavy hitters are close to exact.
+        assert_eq!(sketch.estimate(b"key-0"), 1000);
+        let error = sketch.estimate(b"key-1") - 500;
+        assert!(error <= 40, "{}", error);
+        assert_eq!(sketch.total(), (0..1000u64).map(|i| 1000 / (i + 1)).sum());
+    }
+
+    #[test]
+    fn test_conservative_update_over_counts_less() {
+        let mut sketch = CountMinSketch::new(64, 3);
+        let mut plain = vec![0u64; 64 * 3];
+        for i in 0..2000 {
+            let key = format!("key-{}", i % 500);
+            sketch.increment(key.as_bytes());
+            for cell in sketch.cells(hash64(key.as_bytes(), 0)) {
+                plain[cell] += 1;
+            }
+        }
+        let total_error = |estimate: &dyn Fn(&[u8]) -> u64| -> u64 {
+            (0..500)
+                .map(|i| estimate(format!("key-{}", i).as_bytes()) - 4)
+                .sum()
+        };
+        let conservative = total_error(&|key| sketch.estimate(key));
+        let standard = total_error(&|key| {
+            let cells = sketch.cells(hash64(key, 0));
+            cells.map(|cell| plain[cell]).min().unwrap()
+        });
+        assert!(conservative < standard, "{} vs {}", conservative, standard);
+    }
+
+    #[test]
+    fn test_with_error() {
+        let sketch = CountMinSketch::with_error(0.01, 0.01);
+        assert_eq!(sketch.width(), 272);
+        assert_eq!(sketch.depth(), 5);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut left = CountMinSketch::new(100, 4);
+        let mut right = CountMinSketch::new(100, 4);
+        left.add(b"apple", 3);
+        right.add(b"apple", 4);
+        right.add(b"banana", 5);
+        left.merge(&right).unwrap();
+        assert!(left.estimate(b"apple") >= 7);
+        assert!(left.estimate(b"banana") >= 5);
+        assert_eq!(left.total(), 12);
+
+        assert_eq!(
+            left.merge(&CountMinSketch::new(50, 4)),
+            Err(IncompatibleFilterError::Width {
+                left: 100,
+                right: 50
+            })
+        );
+        assert_eq!(
+            left.merge(&CountMinSketch::new_with_seed(100, 4, 1)),
+            Err(IncompatibleFilterError::Seed { left: 0, right: 1 })
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut sketch = CountMinSketch::new_with_seed(100, 4, 7);
+        for i in 0..1000 {
+            sketch.increment(format!("key-{}", i % 37).as_bytes());
+        }
+        let encoded = sketch.encode();
+        let decoded = CountMinSketch::decode(&encoded).unwrap();
+        for i in 0..37 {
+            let key = format!("key-{}", i);
+            assert_eq!(
+                decoded.estimate(key.as_bytes()),
+                sketch.estimate(key.as_bytes())
+            );
+        }
+        assert_eq!(decoded.encode(), encoded);
+
+        for len in 0..encoded.len() {
+            assert!(CountMinSketch::decode(&encoded[..len]).is_err());
+        }
+        let mut corrupted = encoded.to_vec();
+        corrupted[HEADER_LEN + 20] ^= 1;
+        assert!(matches!(
+            CountMinSketch::decode(&corrupted),
+            Err(FilterDecodeError::ChecksumMismatch { .. })
+        ));
+    }
+}
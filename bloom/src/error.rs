@@ -0,0 +1,197 @@
+use std::fmt;
+
+use crate::prefix::PrefixExtractor;
+
+const CHECKSUM_LEN: usize = 4;
+
+/// Returned when combining two filters or sketches that were not built with the same
+/// parameters, their bits mean different things so they can not be merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncompatibleFilterError {
+    NumBits {
+        left: usize,
+        right: usize,
+    },
+    NumProbes {
+        left: u32,
+        right: u32,
+    },
+    Seed {
+        left: u64,
+        right: u64,
+    },
+    Width {
+        left: usize,
+        right: usize,
+    },
+    Depth {
+        left: u32,
+        right: u32,
+    },
+    Precision {
+        left: u8,
+        right: u8,
+    },
+    PrefixExtractor {
+        left: Option<PrefixExtractor>,
+        right: Option<PrefixExtractor>,
+    },
+}
+
+impl fmt::Display for IncompatibleFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibleFilterError::NumBits { left, right } => {
+                write!(
+                    f,
+                    "filters have different sizes: {} bits vs {} bits",
+                    left, right
+                )
+            }
+            IncompatibleFilterError::NumProbes { left, right } => {
+                write!(
+                    f,
+                    "filters use different probe counts: {} vs {}",
+                    left, right
+                )
+            }
+            IncompatibleFilterError::Seed { left, right } => {
+                write!(f, "filters use different hash seeds: {} vs {}", left, right)
+            }
+            IncompatibleFilterError::Width { left, right } => {
+                write!(f, "sketches have different widths: {} vs {}", left, right)
+            }
+            IncompatibleFilterError::Depth { left, right } => {
+                write!(f, "sketches have different depths: {} vs {}", left, right)
+            }
+            IncompatibleFilterError::Precision { left, right } => {
+                write!(
+                    f,
+                    "sketches have different precisions: {} vs {}",
+                    left, right
+                )
+            }
+            IncompatibleFilterError::PrefixExtractor { left, right } => {
+                write!(
+                    f,
+                    "filters use different prefix extractors: {:?} vs {:?}",
+                    left, right
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for IncompatibleFilterError {}
+
+/// Returned when decoding bytes that are not a valid encoded filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecodeError {
+    /// The input ends before the filter does, `expected` is the minimum length needed.
+    Truncated { expected: usize, actual: usize },
+    /// The input continues after the end of the filter.
+    TrailingBytes { expected: usize, actual: usize },
+    /// The stored checksum does not match the contents, the bytes were corrupted.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Written by a newer format version this build does not understand.
+    UnknownVersion(u8),
+    /// Keys were hashed with an algorithm this build does not implement.
+    UnknownHashAlgorithm(u8),
+    /// Prefixes were extracted in a way this build does not implement.
+    UnknownPrefixExtractor(u8),
+    /// The bits were stored in an encoding this build does not implement.
+    UnknownBitEncoding(u8),
+    /// The bits are Golomb-Rice coded and can not be queried in place, decode the filter.
+    CompressedBits,
+    /// The header is well formed but describes an unusable filter.
+    InvalidHeader(&'static str),
+}
+
+impl fmt::Display for FilterDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterDecodeError::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "filter truncated: need {} bytes, got {}",
+                    expected, actual
+                )
+            }
+            FilterDecodeError::TrailingBytes { expected, actual } => {
+                write!(
+                    f,
+                    "trailing bytes after filter: expected {} bytes, got {}",
+                    expected, actual
+                )
+            }
+            FilterDecodeError::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "filter checksum mismatch: stored {:#010x}, computed {:#010x}",
+                    expected, actual
+                )
+            }
+            FilterDecodeError::UnknownVersion(version) => {
+                write!(f, "unknown filter format version {}", version)
+            }
+            FilterDecodeError::UnknownHashAlgorithm(id) => {
+                write!(f, "unknown filter hash algorithm {}", id)
+            }
+            FilterDecodeError::UnknownPrefixExtractor(id) => {
+                write!(f, "unknown filter prefix extractor {}", id)
+            }
+            FilterDecodeError::UnknownBitEncoding(id) => {
+                write!(f, "unknown filter bit encoding {}", id)
+            }
+            FilterDecodeError::CompressedBits => {
+                write!(f, "filter bits are golomb-rice coded and can not be viewed")
+            }
+            FilterDecodeError::InvalidHeader(reason) => {
+                write!(f, "invalid filter header: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FilterDecodeError {}
+
+/// Check the crc32 at the end of `encoded` and return the bytes it covers.
+pub(crate) fn verify_checksum(encoded: &[u8]) -> Result<&[u8], FilterDecodeError> {
+    ensure_remaining(encoded, CHECKSUM_LEN, 0)?;
+    let (body, checksum) = encoded.split_at(encoded.len() - CHECKSUM_LEN);
+    let stored = u32::from_be_bytes(checksum.try_into().expect("checksum is 4 bytes"));
+    let computed = crc32fast::hash(body);
+    if stored != computed {
+        return Err(FilterDecodeError::ChecksumMismatch {
+            expected: stored,
+            actual: computed,
+        });
+    }
+    Ok(body)
+}
+
+/// Fail with `TrailingBytes` unless `buf`, the unread rest of `encoded`, is empty.
+pub(crate) fn ensure_consumed(buf: &[u8], encoded: &[u8]) -> Result<(), FilterDecodeError> {
+    if !buf.is_empty() {
+        return Err(FilterDecodeError::TrailingBytes {
+            expected: encoded.len() - buf.len(),
+            actual: encoded.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Fail with `Truncated` unless `buf` still holds `needed` bytes, `consumed` bytes into the input.
+pub(crate) fn ensure_remaining(
+    buf: &[u8],
+    needed: usize,
+    consumed: usize,
+) -> Result<(), FilterDecodeError> {
+    if buf.len() < needed {
+        return Err(FilterDecodeError::Truncated {
+            expected: consumed.saturating_add(needed),
+            actual: consumed + buf.len(),
+        });
+    }
+    Ok(())
+}
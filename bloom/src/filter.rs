@@ -0,0 +1,60 @@
+use bytes::Bytes;
+
+/// Approximate membership filter, gives a FIRM no or a PROBABLY yes.
+///
+/// Implemented by every filter in the crate so callers can swap one for another. Keys are
+/// anything that can be viewed as bytes rather than `K: Hash`: `Hash` implementations feed a
+/// `std::hash::Hasher`, whose output is not stable, and encoded filters must hash keys the
+/// same way in every build.
+pub trait Filter {
+    /// Add a key to the filter.
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K);
+
+    /// Return false if the key was definitely never inserted, true if it may have been.
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool;
+
+    /// Estimated number of distinct keys inserted.
+    fn estimated_len(&self) -> usize;
+
+    /// Encode the filter so it can be stored, each filter type documents its layout.
+    fn encode(&self) -> Bytes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocked::BlockedBloomFilter;
+    use crate::concurrent::ConcurrentBloomFilter;
+    use crate::counting::CountingBloomFilter;
+    use crate::cuckoo::CuckooFilter;
+    use crate::scalable::ScalableBloomFilter;
+    use crate::simple::SimpleBloomFilter;
+    use crate::slatedb::SlatedbBloomFilter;
+    use crate::stable::StableBloomFilter;
+
+    fn check_filter<F: Filter>(mut filter: F) {
+        filter.insert("apple");
+        filter.insert(String::from("banana"));
+        filter.insert(Vec::from("orange"));
+        filter.insert(&b"kiwi"[..]);
+
+        for key in ["apple", "banana", "orange", "kiwi"] {
+            assert!(filter.may_contain(key));
+        }
+        assert!(!filter.may_contain("grape"));
+        assert_eq!(filter.estimated_len(), 4);
+        assert!(!filter.encode().is_empty());
+    }
+
+    #[test]
+    fn test_all_filters_implement_filter() {
+        check_filter(SimpleBloomFilter::new(1000, 3));
+        check_filter(SlatedbBloomFilter::new(1000, 3));
+        check_filter(CountingBloomFilter::new(1000, 3));
+        check_filter(BlockedBloomFilter::new(1000, 3));
+        check_filter(ConcurrentBloomFilter::new(1000, 3));
+        check_filter(ScalableBloomFilter::new(100, 0.01));
+        check_filter(CuckooFilter::new(1000, 0.01));
+        check_filter(StableBloomFilter::new(1000, 3, 3, 1));
+    }
+}
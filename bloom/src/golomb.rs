@@ -0,0 +1,152 @@
+//! Golomb-Rice coding of sorted integers, used to store the set bits of sparse filters.
+//!
+//! Each gap between consecutive values is split into a quotient, written in unary, and the
+//! low `k` bits, written as is. Gaps of a random sparse set are roughly geometric, for which
+//! this is close to the entropy when `2^k` is near the mean gap.
+//! https://en.wikipedia.org/wiki/Golomb_coding#Rice_coding
+
+/// Golomb-Rice parameter for `count` values spread over `0..range`.
+pub(crate) fn rice_parameter(count: usize, range: usize) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let mean_gap = (range / count).max(1);
+    mean_gap.ilog2() as u8
+}
+
+/// Encode strictly increasing `values` with parameter `k`.
+pub(crate) fn encode(values: impl Iterator<Item = usize>, k: u8) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    let mut next = 0;
+    for value in values {
+        debug_assert!(value >= next, "values must be strictly increasing");
+        let gap = (value - next) as u64;
+        for _ in 0..gap >> k {
+            writer.push(true);
+        }
+        writer.push(false);
+        for bit in (0..k).rev() {
+            writer.push(gap >> bit & 1 == 1);
+        }
+        next = value + 1;
+    }
+    writer.bytes
+}
+
+/// Decode `count` values written by [`encode`], None if `encoded` ends early, a value is not
+/// below `limit` or whole bytes are left over.
+pub(crate) fn decode(encoded: &[u8], count: usize, k: u8, limit: usize) -> Option<Vec<usize>> {
+    let mut reader = BitReader {
+        bytes: encoded,
+        pos: 0,
+    };
+    let mut values = Vec::with_capacity(count.min(encoded.len() * 8));
+    let mut next = 0usize;
+    for _ in 0..count {
+        let mut quotient = 0usize;
+        while reader.next()? {
+            quotient += 1;
+        }
+        let mut remainder = 0usize;
+        for _ in 0..k {
+            remainder = remainder << 1 | reader.next()? as usize;
+        }
+        let value = quotient
+            .checked_shl(k as u32)
+            .filter(|shifted| shifted >> k == quotient)?
+            .checked_add(remainder)?
+            .checked_add(next)?;
+        if value >= limit {
+            return None;
+        }
+        values.push(value);
+        next = value + 1;
+    }
+    if reader.pos.div_ceil(8) != encoded.len() {
+        return None;
+    }
+    Some(values)
+}
+
+/// Appends bits most significant first, the last byte is padded with zeros.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().expect("a byte was pushed") |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn next(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.pos / 8)?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Some(bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let values = vec![0, 1, 2, 7, 100, 101, 5000, 5001, 99_999];
+        for k in [0, 3, 6, 12] {
+            let encoded = encode(values.iter().copied(), k);
+            assert_eq!(
+                decode(&encoded, values.len(), k, 100_000),
+                Some(values.clone())
+            );
+        }
+        assert_eq!(decode(&[], 0, 3, 10), Some(vec![]));
+    }
+
+    #[test]
+    fn test_known_encoding() {
+        // k = 2: gap 5 is quotient 1 "10" and remainder "01", gap 0 (6) is "0" "00".
+        assert_eq!(encode([5, 6].into_iter(), 2), vec![0b1001_0000]);
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_input() {
+        let encoded = encode([3, 9, 40].into_iter(), 3);
+        // more values than were written runs out of bits.
+        assert_eq!(decode(&encoded, 10, 3, 100), None);
+        // values past the limit.
+        assert_eq!(decode(&encoded, 3, 3, 40), None);
+        // a run of ones that never ends.
+        assert_eq!(decode(&[0xff; 4], 1, 3, 100), None);
+        // bytes after the last value.
+        let mut longer = encoded.clone();
+        longer.push(0);
+        assert_eq!(decode(&longer, 3, 3, 100), None);
+    }
+
+    #[test]
+    fn test_sparse_set_is_compact() {
+        // 1000 of 100_000 positions, mean gap 100.
+        let values: Vec<usize> = (0..1000).map(|i| i * 100 + i % 7).collect();
+        let k = rice_parameter(values.len(), 100_000);
+        assert_eq!(k, 6);
+        let encoded = encode(values.iter().copied(), k);
+        // ~8 bits per value against 100 bits per value in a bitmap.
+        assert!(encoded.len() < 1100, "{}", encoded.len());
+    }
+}
@@ -0,0 +1,75 @@
+use xxhash_rust::xxh64::xxh64;
+
+/// Seed used by filters that are not given one explicitly.
+pub(crate) const DEFAULT_SEED: u64 = 0;
+/// Id of the key hash in encoded forms: xxHash64 with double hashing.
+pub(crate) const HASH_XXH64: u8 = 1;
+
+/// Hash a key with xxHash64.
+///
+/// Unlike `std::hash::DefaultHasher`, the output is pinned by the xxHash specification,
+/// so a filter encoded by one build can be read by a binary built with any other toolchain.
+/// https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md
+pub(crate) fn hash64(key: &[u8], seed: u64) -> u64 {
+    xxh64(key, seed)
+}
+
+/// Map a key to `num_probes` positions in `0..num_bits`, without allocating.
+///
+/// Uses the double hashing scheme `g_i(x) = h1(x) + i * h2(x)`, where `h1` and `h2` are
+/// the low and high halves of a single 64-bit hash, see README.md.
+pub(crate) fn probes(
+    key: &[u8],
+    seed: u64,
+    num_probes: u32,
+    num_bits: usize,
+) -> impl Iterator<Item = usize> {
+    probes_from_hash(hash64(key, seed), num_probes, num_bits)
+}
+
+/// The positions of `probes` for a key whose `hash64` is already known.
+pub(crate) fn probes_from_hash(
+    hash: u64,
+    num_probes: u32,
+    num_bits: usize,
+) -> impl Iterator<Item = usize> {
+    let size = num_bits as u64;
+    // double hashing scheme to generate multiple unique indices from a single hash value.
+    let h1 = (hash << 32) >> 32;
+    let h2 = hash >> 32;
+    (0..num_probes).map(move |i| ((h1.wrapping_add(i as u64 * h2)) % size) as usize)
+}
+
+/// Map a 32-bit value onto `0..n` without a division, see Lemire's fastrange.
+/// https://lemire.me/blog/2016/06/27/a-fast-alternative-to-the-modulo-reduction/
+pub(crate) fn reduce(x: u32, n: usize) -> usize {
+    ((x as u64 * n as u64) >> 32) as usize
+}
+
+/// Advance a splitmix64 generator and return its next output.
+/// https://prng.di.unimi.it/splitmix64.c
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash64_matches_reference_vectors() {
+        // Reference values from the xxHash test suite.
+        assert_eq!(hash64(b"", 0), 0xEF46DB3751D8E999);
+        assert_eq!(hash64(b"a", 0), 0xD24EC4F1A98C6E5B);
+        assert_eq!(hash64(b"abc", 0), 0x44BC2CF5AD770999);
+    }
+
+    #[test]
+    fn test_seed_changes_hash() {
+        assert_ne!(hash64(b"apple", 0), hash64(b"apple", 1));
+    }
+}
@@ -0,0 +1,386 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{
+    ensure_consumed, ensure_remaining, verify_checksum, FilterDecodeError, IncompatibleFilterError,
+};
+use crate::hash::{hash64, DEFAULT_SEED, HASH_XXH64};
+
+/// Version of the encoded sketch layout, bumped on any incompatible change.
+const FORMAT_VERSION: u8 = 1;
+/// version, hash id, precision, seed, representation
+const HEADER_LEN: usize = 1 + 1 + 1 + 8 + 1;
+const MIN_PRECISION: u8 = 4;
+const MAX_PRECISION: u8 = 18;
+/// Ids of the register representations in the encoded form.
+const SPARSE: u8 = 0;
+const DENSE: u8 = 1;
+
+/// HyperLogLog, estimates the number of distinct keys in a stream in a few KB.
+///
+/// The top `precision` bits of a key's hash pick one of `2^precision` registers, which keeps
+/// the longest run of leading zeros seen in the rest of the hash. Many distinct keys are
+/// needed to see a long run, so the registers together estimate the count with a relative
+/// standard error of `1.04 / sqrt(2^precision)`, 1.6% at the default precision of 12.
+///
+/// Registers start out sparse, a sorted list of the non-zero ones, and switch to one byte per
+/// register once that is smaller, so a sketch of a few keys stays a few bytes. Sketches merge
+/// losslessly: the merge of two sketches is the sketch of both streams.
+/// http://algo.inria.fr/flajolet/Publications/FlFuGaMe07.pdf
+/// https://research.google/pubs/hyperloglog-in-practice-algorithmic-engineering-of-a-state-of-the-art-cardinality-estimation-algorithm/
+#[derive(Clone, Debug, PartialEq)]
+pub struct HyperLogLog {
+    registers: Registers,
+    precision: u8,
+    seed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Registers {
+    /// `index << 8 | rank` of every non-zero register, sorted by index.
+    Sparse(Vec<u32>),
+    /// One rank per register.
+    Dense(Vec<u8>),
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u8) -> Self {
+        Self::new_with_seed(precision, DEFAULT_SEED)
+    }
+
+    pub fn new_with_seed(precision: u8, seed: u64) -> Self {
+        assert!(
+            (MIN_PRECISION..=MAX_PRECISION).contains(&precision),
+            "precision must be in {}..={}, got {}",
+            MIN_PRECISION,
+            MAX_PRECISION,
+            precision
+        );
+        Self {
+            registers: Registers::Sparse(Vec::new()),
+            precision,
+            seed,
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.registers, Registers::Sparse(_))
+    }
+
+    /// Number of bytes used by the sketch, including the registers.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match &self.registers {
+                Registers::Sparse(entries) => entries.capacity() * std::mem::size_of::<u32>(),
+                Registers::Dense(ranks) => ranks.capacity(),
+            }
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        let hash = hash64(key, self.seed);
+        let index = (hash >> (64 - self.precision)) as usize;
+        // the guard bit caps the rank at 64 - precision + 1 when the rest is all zeros.
+        let rank = ((hash << self.precision) | (1 << (self.precision - 1))).leading_zeros() + 1;
+        self.set_register(index, rank as u8);
+    }
+
+    /// Estimated number of distinct keys added.
+    pub fn estimate(&self) -> f64 {
+        let m = self.num_registers() as f64;
+        let (sum, zeros) = match &self.registers {
+            Registers::Sparse(entries) => {
+                let sum: f64 = entries.iter().map(|entry| rank_weight(*entry as u8)).sum();
+                let zeros = self.num_registers() - entries.len();
+                (sum + zeros as f64, zeros)
+            }
+            Registers::Dense(ranks) => {
+                let sum: f64 = ranks.iter().map(|rank| rank_weight(*rank)).sum();
+                (sum, ranks.iter().filter(|rank| **rank == 0).count())
+            }
+        };
+        let raw = alpha(m) * m * m / sum;
+        if raw <= 2.5 * m && zeros > 0 {
+            // small range correction, linear counting over the empty registers.
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Take in the keys of `other`, afterwards the sketch estimates the distinct keys of both.
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), IncompatibleFilterError> {
+        if self.precision != other.precision {
+            return Err(IncompatibleFilterError::Precision {
+                left: self.precision,
+                right: other.precision,
+            });
+        }
+        if self.seed != other.seed {
+            return Err(IncompatibleFilterError::Seed {
+                left: self.seed,
+                right: other.seed,
+            });
+        }
+        match &other.registers {
+            Registers::Sparse(entries) => {
+                for entry in entries {
+                    self.set_register((entry >> 8) as usize, *entry as u8);
+                }
+            }
+            Registers::Dense(ranks) => {
+                self.densify();
+                if let Registers::Dense(own) = &mut self.registers {
+                    for (own, rank) in own.iter_mut().zip(ranks) {
+                        *own = (*own).max(*rank);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode a sketch written by [`HyperLogLog::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<HyperLogLog, FilterDecodeError> {
+        let body = verify_checksum(encoded)?;
+        let mut buf = body;
+        ensure_remaining(buf, HEADER_LEN, 0)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(FilterDecodeError::UnknownVersion(version));
+        }
+        let hash_algorithm = buf.get_u8();
+        if hash_algorithm != HASH_XXH64 {
+            return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+        }
+        let precision = buf.get_u8();
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(FilterDecodeError::InvalidHeader("precision out of range"));
+        }
+        let seed = buf.get_u64();
+        let num_registers = 1usize << precision;
+        let max_rank = 64 - precision + 1;
+        let registers = match buf.get_u8() {
+            SPARSE => {
+                ensure_remaining(buf, 4, HEADER_LEN)?;
+                let len = buf.get_u32() as usize;
+                ensure_remaining(buf, len.saturating_mul(4), HEADER_LEN + 4)?;
+                let entries: Vec<u32> = (0..len).map(|_| buf.get_u32()).collect();
+                let valid = entries.iter().all(|entry| {
+                    let rank = *entry as u8;
+                    (entry >> 8) < num_registers as u32 && rank > 0 && rank <= max_rank
+                });
+                if !valid || entries.windows(2).any(|pair| pair[0] >> 8 >= pair[1] >> 8) {
+                    return Err(FilterDecodeError::InvalidHeader("invalid sparse registers"));
+                }
+                Registers::Sparse(entries)
+            }
+            DENSE => {
+                ensure_remaining(buf, num_registers, HEADER_LEN)?;
+                let ranks = buf[..num_registers].to_vec();
+                buf.advance(num_registers);
+                if ranks.iter().any(|rank| *rank > max_rank) {
+                    return Err(FilterDecodeError::InvalidHeader("register out of range"));
+                }
+                Registers::Dense(ranks)
+            }
+            _ => {
+                return Err(FilterDecodeError::InvalidHeader(
+                    "unknown register representation",
+                ))
+            }
+        };
+        ensure_consumed(buf, body)?;
+        Ok(Self {
+            registers,
+            precision,
+            seed,
+        })
+    }
+
+    /// Encode the sketch.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | precision (u8) | seed (u64) | representation (u8) |
+    /// | sparse: count (u32) | index << 8 | rank (u32) ... | or dense: ranks (2^precision u8) |
+    /// | crc32 of everything before it (u32) |
+    /// ```
+    pub fn encode(&self) -> Bytes {
+        let mut encoded = BytesMut::new();
+        encoded.put_u8(FORMAT_VERSION);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u8(self.precision);
+        encoded.put_u64(self.seed);
+        match &self.registers {
+            Registers::Sparse(entries) => {
+                encoded.put_u8(SPARSE);
+                encoded.put_u32(entries.len() as u32);
+                for entry in entries {
+                    encoded.put_u32(*entry);
+                }
+            }
+            Registers::Dense(ranks) => {
+                encoded.put_u8(DENSE);
+                encoded.put_slice(ranks);
+            }
+        }
+        encoded.put_u32(crc32fast::hash(&encoded));
+        encoded.freeze()
+    }
+
+    fn num_registers(&self) -> usize {
+        1 << self.precision
+    }
+
+    /// Raise register `index` to `rank` if it is lower.
+    fn set_register(&mut self, index: usize, rank: u8) {
+        let num_registers = self.num_registers();
+        match &mut self.registers {
+            Registers::Sparse(entries) => {
+                let entry = (index as u32) << 8 | rank as u32;
+                match entries.binary_search_by_key(&index, |entry| (entry >> 8) as usize) {
+                    Ok(position) => {
+                        if (entries[position] as u8) < rank {
+                            entries[position] = entry;
+                        }
+                    }
+                    Err(position) => {
+                        entries.insert(position, entry);
+                        // 4 bytes per sparse entry against 1 byte per dense register.
+                        if entries.len() * 4 > num_registers {
+                            self.densify();
+                        }
+                    }
+                }
+            }
+            Registers::Dense(ranks) => ranks[index] = ranks[index].max(rank),
+        }
+    }
+
+    fn densify(&mut self) {
+        if let Registers::Sparse(entries) = &self.registers {
+            let mut ranks = vec![0u8; self.num_registers()];
+            for entry in entries {
+                ranks[(entry >> 8) as usize] = *entry as u8;
+            }
+            self.registers = Registers::Dense(ranks);
+        }
+    }
+}
+
+/// `2^-rank`, the share of the harmonic mean of one register.
+fn rank_weight(rank: u8) -> f64 {
+    1.0 / (1u64 << rank) as f64
+}
+
+/// Bias correction constant for `m` registers.
+fn alpha(m: f64) -> f64 {
+    match m as usize {
+        16 => 0.673,
+        32 => 0.697,
+        64 => 0.709,
+        _ => 0.7213 / (1.0 + 1.079 / m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch_with_keys(precision: u8, keys: std::ops::Range<usize>) -> HyperLogLog {
+        let mut sketch = HyperLogLog::new(precision);
+        for i in keys {
+            sketch.add_key(format!("key-{}", i).as_bytes());
+        }
+        sketch
+    }
+
+    fn assert_close(estimate: f64, actual: usize, tolerance: f64) {
+        let error = (estimate - actual as f64).abs() / actual as f64;
+        assert!(
+            error < tolerance,
+            "estimate {} for {} keys, error {:.4}",
+            estimate,
+            actual,
+            error
+        );
+    }
+
+    #[test]
+    fn test_estimate() {
+        // 1.6% standard error at precision 12, allow 3 sigma.
+        for num_keys in [10, 100, 1000, 10_000, 100_000, 1_000_000] {
+            assert_close(sketch_with_keys(12, 0..num_keys).estimate(), num_keys, 0.05);
+        }
+        assert_eq!(HyperLogLog::new(12).estimate(), 0.0);
+    }
+
+    #[test]
+    fn test_duplicates_are_not_counted() {
+        let mut sketch = HyperLogLog::new(12);
+        for i in 0..100_000 {
+            sketch.add_key(format!("key-{}", i % 1000).as_bytes());
+        }
+        assert_close(sketch.estimate(), 1000, 0.05);
+    }
+
+    #[test]
+    fn test_sparse_until_smaller_dense() {
+        let mut sketch = HyperLogLog::new(12);
+        for i in 0..100 {
+            sketch.add_key(format!("key-{}", i).as_bytes());
+        }
+        assert!(sketch.is_sparse());
+        assert!(sketch.encode().len() < 500);
+        for i in 100..2000 {
+            sketch.add_key(format!("key-{}", i).as_bytes());
+        }
+        assert!(!sketch.is_sparse());
+        assert_eq!(sketch.encode().len(), HEADER_LEN + 4096 + 4);
+    }
+
+    #[test]
+    fn test_merge() {
+        for (left, right) in [(0..100, 50..150), (0..50_000, 25_000..75_000)] {
+            let mut merged = sketch_with_keys(12, left.clone());
+            merged.merge(&sketch_with_keys(12, right.clone())).unwrap();
+            let union = left.start.min(right.start)..left.end.max(right.end);
+            // registers are the same as a sketch of the union, so the estimate is too.
+            let mut expected = sketch_with_keys(12, union);
+            expected.densify();
+            merged.densify();
+            assert_eq!(merged, expected);
+        }
+        // sparse into dense and dense into sparse.
+        let mut sparse = sketch_with_keys(12, 0..10);
+        sparse.merge(&sketch_with_keys(12, 0..5000)).unwrap();
+        assert_close(sparse.estimate(), 5000, 0.05);
+        let mut dense = sketch_with_keys(12, 0..5000);
+        dense.merge(&sketch_with_keys(12, 5000..5010)).unwrap();
+        assert_close(dense.estimate(), 5010, 0.05);
+
+        assert_eq!(
+            sparse.merge(&HyperLogLog::new(10)),
+            Err(IncompatibleFilterError::Precision {
+                left: 12,
+                right: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        for sketch in [sketch_with_keys(10, 0..20), sketch_with_keys(10, 0..5000)] {
+            let encoded = sketch.encode();
+            let decoded = HyperLogLog::decode(&encoded).unwrap();
+            assert_eq!(decoded, sketch);
+            assert_eq!(decoded.encode(), encoded);
+            for len in 0..encoded.len() {
+                assert!(HyperLogLog::decode(&encoded[..len]).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+pub mod blocked;
+pub mod cache;
+pub mod concurrent;
+pub mod counting;
+pub mod countmin;
+pub mod cuckoo;
+pub mod error;
+pub mod filter;
+mod golomb;
+mod hash;
+pub mod hyperloglog;
+pub mod lsm;
+mod nibbles;
+pub mod prefix;
+pub mod ribbon;
+pub mod scalable;
+pub mod simple;
+pub mod slatedb;
+pub mod stable;
+pub mod xor;
+
+pub use filter::Filter;
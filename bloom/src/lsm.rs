@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::slatedb::{BloomFilterSize, SlatedbBloomFilter};
+
+/// Suffix of sorted run files, the rest of the name is the run id.
+const RUN_EXTENSION: &str = "run";
+/// data_len, filter_len
+const FOOTER_LEN: usize = 8 + 8;
+/// Value length marking a deleted key.
+const TOMBSTONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DbOptions {
+    /// Flush the memtable to a new sorted run once its keys and values take this many bytes.
+    pub memtable_bytes: usize,
+    /// Bits per key of the bloom filter stored with each sorted run.
+    pub bits_per_key: u32,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            memtable_bytes: 1 << 20,
+            bits_per_key: 10,
+        }
+    }
+}
+
+/// Counters of what `get` did, to see how many sorted run reads the filters saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadStats {
+    pub gets: u64,
+    /// Runs the filter ruled out, each one a read that was avoided.
+    pub runs_skipped: u64,
+    /// Runs read from disk because the filter said the key may be there.
+    pub runs_read: u64,
+    /// Runs read for nothing, the filter said maybe but the key was not there.
+    pub false_positives: u64,
+}
+
+/// Embedded key-value store laid out like a tiny LSM tree, SlateDB style.
+///
+/// Writes go to an in-memory memtable. Once it is large enough it is flushed to an immutable
+/// sorted run file, which stores the sorted entries followed by an encoded
+/// [`SlatedbBloomFilter`] of its keys. Filters stay in memory, so a point `get` checks the
+/// memtable and then the runs newest first, and only reads a run from disk if its filter says
+/// the key may be there.
+///
+/// There is no write-ahead log, writes still in the memtable are lost unless `flush` is
+/// called before the store is dropped. Runs are never compacted.
+pub struct Db {
+    dir: PathBuf,
+    options: DbOptions,
+    /// `None` is a tombstone, it hides the key in older runs.
+    memtable: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    memtable_bytes: usize,
+    /// Newest first.
+    runs: Vec<SortedRun>,
+    next_run_id: u64,
+    gets: AtomicU64,
+    runs_skipped: AtomicU64,
+    runs_read: AtomicU64,
+    false_positives: AtomicU64,
+}
+
+struct SortedRun {
+    path: PathBuf,
+    filter: SlatedbBloomFilter,
+    /// Length of the entries at the start of the file.
+    data_len: usize,
+}
+
+impl Db {
+    /// Open the store in `dir`, creating the directory if needed and loading the filters of
+    /// the sorted runs already there.
+    pub fn open(dir: impl AsRef<Path>, options: DbOptions) -> io::Result<Db> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == RUN_EXTENSION) {
+                if let Some(id) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        let runs = ids
+            .iter()
+            .map(|id| SortedRun::open(run_path(&dir, *id)))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Db {
+            dir,
+            options,
+            memtable: BTreeMap::new(),
+            memtable_bytes: 0,
+            runs,
+            next_run_id: ids.first().map_or(0, |id| id + 1),
+            gets: AtomicU64::new(0),
+            runs_skipped: AtomicU64::new(0),
+            runs_read: AtomicU64::new(0),
+            false_positives: AtomicU64::new(0),
+        })
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.write(key, Some(value))
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<()> {
+        self.write(key, None)
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<&[u8]>) -> io::Result<()> {
+        let value_len = value.map_or(0, <[u8]>::len);
+        if key.len() >= TOMBSTONE as usize || value_len >= TOMBSTONE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "keys and values must be shorter than 4GiB",
+            ));
+        }
+        self.memtable_bytes += key.len() + value_len;
+        if let Some(replaced) = self
+            .memtable
+            .insert(key.to_vec(), value.map(<[u8]>::to_vec))
+        {
+            self.memtable_bytes -= key.len() + replaced.map_or(0, |value| value.len());
+        }
+        if self.memtable_bytes >= self.options.memtable_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Latest value of `key`, reading only the sorted runs whose filter may hold it.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(value.clone());
+        }
+        for run in &self.runs {
+            if !run.filter.has_key(key) {
+                self.runs_skipped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.runs_read.fetch_add(1, Ordering::Relaxed);
+            match run.get(key)? {
+                Some(value) => return Ok(value),
+                None => {
+                    self.false_positives.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Write the memtable to a new sorted run, a no-op if it is empty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let path = run_path(&self.dir, self.next_run_id);
+        let run = SortedRun::write(path, &self.memtable, self.options.bits_per_key)?;
+        self.runs.insert(0, run);
+        self.next_run_id += 1;
+        self.memtable.clear();
+        self.memtable_bytes = 0;
+        Ok(())
+    }
+
+    pub fn num_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn stats(&self) -> ReadStats {
+        ReadStats {
+            gets: self.gets.load(Ordering::Relaxed),
+            runs_skipped: self.runs_skipped.load(Ordering::Relaxed),
+            runs_read: self.runs_read.load(Ordering::Relaxed),
+            false_positives: self.false_positives.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl SortedRun {
+    /// Write `entries` to `path`.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | entries | filter (SlatedbBloomFilter::encode) | data_len (u64) | filter_len (u64) |
+    /// ```
+    /// Each entry is `| key_len (u32) | key | value_len (u32) | value |`, sorted by key, with
+    /// a value length of `u32::MAX` and no value for a deleted key. The file is written
+    /// and synced under a temporary name, renamed, and the directory synced, so a crash never
+    /// leaves a partial run behind.
+    fn write(
+        path: PathBuf,
+        entries: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        bits_per_key: u32,
+    ) -> io::Result<SortedRun> {
+        let size = BloomFilterSize::with_bits_per_key(entries.len(), bits_per_key);
+        let mut filter = SlatedbBloomFilter::with_size(size);
+        let mut encoded = BytesMut::new();
+        for (key, value) in entries {
+            filter.add_key(key);
+            encoded.put_u32(key.len() as u32);
+            encoded.put_slice(key);
+            match value {
+                Some(value) => {
+                    encoded.put_u32(value.len() as u32);
+                    encoded.put_slice(value);
+                }
+                None => encoded.put_u32(TOMBSTONE),
+            }
+        }
+        let data_len = encoded.len();
+        let encoded_filter = filter.encode();
+        encoded.put_slice(&encoded_filter);
+        encoded.put_u64(data_len as u64);
+        encoded.put_u64(encoded_filter.len() as u64);
+
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&encoded)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+        if let Some(dir) = path.parent() {
+            fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(SortedRun {
+            path,
+            filter,
+            data_len,
+        })
+    }
+
+    /// Load the filter of a run written by [`SortedRun::write`], leaving the entries on disk.
+    fn open(path: PathBuf) -> io::Result<SortedRun> {
+        let mut file = fs::File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        if file_len < FOOTER_LEN as u64 {
+            return Err(invalid_data(&path, "shorter than the footer"));
+        }
+        file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        let mut footer = [0u8; FOOTER_LEN];
+        file.read_exact(&mut footer)?;
+        let mut footer = &footer[..];
+        let data_len = footer.get_u64();
+        let filter_len = footer.get_u64();
+        if data_len.checked_add(filter_len) != Some(file_len - FOOTER_LEN as u64) {
+            return Err(invalid_data(&path, "footer does not match the file length"));
+        }
+
+        file.seek(SeekFrom::Start(data_len))?;
+        let mut encoded_filter = vec![0u8; filter_len as usize];
+        file.read_exact(&mut encoded_filter)?;
+        let filter = SlatedbBloomFilter::decode(&encoded_filter)
+            .map_err(|err| invalid_data(&path, &err.to_string()))?;
+        Ok(SortedRun {
+            path,
+            filter,
+            data_len: data_len as usize,
+        })
+    }
+
+    /// Read the entries from disk and look up `key`, `Some(None)` if it was deleted.
+    fn get(&self, key: &[u8]) -> io::Result<Option<Option<Vec<u8>>>> {
+        let mut file = fs::File::open(&self.path)?;
+        let mut data = vec![0u8; self.data_len];
+        file.read_exact(&mut data)?;
+        let mut buf = &data[..];
+        while buf.has_remaining() {
+            let (entry_key, value) =
+                read_entry(&mut buf).ok_or_else(|| invalid_data(&self.path, "truncated entry"))?;
+            match entry_key.cmp(key) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => return Ok(Some(value.map(<[u8]>::to_vec))),
+                // entries are sorted, the key is not in this run.
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Read one `| key_len | key | value_len | value |` entry, None if `buf` ends inside it.
+fn read_entry<'a>(buf: &mut &'a [u8]) -> Option<(&'a [u8], Option<&'a [u8]>)> {
+    let key = read_slice(buf)?;
+    if buf.remaining() < 4 {
+        return None;
+    }
+    if buf[..4] == TOMBSTONE.to_be_bytes() {
+        buf.advance(4);
+        return Some((key, None));
+    }
+    Some((key, Some(read_slice(buf)?)))
+}
+
+/// Read a u32 length followed by that many bytes.
+fn read_slice<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    let (slice, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(slice)
+}
+
+fn run_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, RUN_EXTENSION))
+}
+
+fn invalid_data(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_memtable() -> DbOptions {
+        DbOptions {
+            memtable_bytes: 1000,
+            bits_per_key: 10,
+        }
+    }
+
+    #[test]
+    fn test_put_get_across_flushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), small_memtable()).unwrap();
+        for i in 0..1000 {
+            db.put(
+                format!("key-{}", i).as_bytes(),
+                format!("value-{}", i).as_bytes(),
+            )
+            .unwrap();
+        }
+        assert!(db.num_runs() > 10, "{}", db.num_runs());
+        for i in 0..1000 {
+            assert_eq!(
+                db.get(format!("key-{}", i).as_bytes()).unwrap(),
+                Some(format!("value-{}", i).into_bytes())
+            );
+        }
+        assert_eq!(db.get(b"key-1000").unwrap(), None);
+    }
+
+    #[test]
+    fn test_newest_value_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), DbOptions::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+        db.put(b"banana", b"yellow").unwrap();
+        db.flush().unwrap();
+        db.put(b"apple", b"green").unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+        db.flush().unwrap();
+        assert_eq!(db.get(b"apple").unwrap(), Some(b"green".to_vec()));
+
+        db.delete(b"banana").unwrap();
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        db.flush().unwrap();
+        // the tombstone in the newest run hides the value in the oldest.
+        assert_eq!(db.get(b"banana").unwrap(), None);
+        assert_eq!(db.num_runs(), 3);
+    }
+
+    #[test]
+    fn test_overwrites_do_not_fill_the_memtable() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), small_memtable()).unwrap();
+        for i in 0..1000 {
+            db.put(b"key", format!("value-{}", i % 10).as_bytes())
+                .unwrap();
+        }
+        db.delete(b"key").unwrap();
+        assert_eq!(db.memtable_bytes, 3);
+        assert_eq!(db.num_runs(), 0);
+    }
+
+    #[test]
+    fn test_too_large_value_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), DbOptions::default()).unwrap();
+        let value = vec![0u8; TOMBSTONE as usize];
+        let err = db.put(b"key", &value).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(db.get(b"key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_reopen_loads_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), small_memtable()).unwrap();
+        for i in 0..500 {
+            db.put(format!("key-{}", i).as_bytes(), b"v1").unwrap();
+        }
+        db.put(b"key-7", b"v2").unwrap();
+        db.flush().unwrap();
+        let num_runs = db.num_runs();
+        drop(db);
+
+        let mut db = Db::open(dir.path(), small_memtable()).unwrap();
+        assert_eq!(db.num_runs(), num_runs);
+        assert_eq!(db.get(b"key-7").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(db.get(b"key-8").unwrap(), Some(b"v1".to_vec()));
+        // new runs are newer than the loaded ones.
+        db.put(b"key-8", b"v3").unwrap();
+        db.flush().unwrap();
+        drop(db);
+        let db = Db::open(dir.path(), small_memtable()).unwrap();
+        assert_eq!(db.get(b"key-8").unwrap(), Some(b"v3".to_vec()));
+    }
+
+    #[test]
+    fn test_filters_avoid_run_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), DbOptions::default()).unwrap();
+        for run in 0..10 {
+            for i in 0..1000 {
+                db.put(format!("key-{}-{}", run, i).as_bytes(), b"value")
+                    .unwrap();
+            }
+            db.flush().unwrap();
+        }
+
+        for i in 0..1000 {
+            assert_eq!(db.get(format!("absent-{}", i).as_bytes()).unwrap(), None);
+        }
+        let stats = db.stats();
+        assert_eq!(stats.gets, 1000);
+        assert_eq!(stats.runs_skipped + stats.runs_read, 10 * 1000);
+        // ~1% false positives at 10 bits per key.
+        assert!(stats.runs_read < 200, "{:?}", stats);
+        assert_eq!(stats.false_positives, stats.runs_read);
+
+        // a key in the oldest run is found after skipping, or reading, every newer run.
+        assert_eq!(db.get(b"key-0-5").unwrap(), Some(b"value".to_vec()));
+        let after = db.stats();
+        assert_eq!(
+            after.runs_skipped + after.runs_read,
+            stats.runs_skipped + stats.runs_read + 10
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_corrupt_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Db::open(dir.path(), DbOptions::default()).unwrap();
+        db.put(b"apple", b"red").unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let path = run_path(dir.path(), 0);
+        let mut bytes = fs::read(&path).unwrap();
+        // last byte of the filter bits, right before its checksum.
+        let last_filter_byte = bytes.len() - FOOTER_LEN - 4 - 1;
+        bytes[last_filter_byte] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let err = Db::open(dir.path(), DbOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        fs::write(&path, b"short").unwrap();
+        let err = Db::open(dir.path(), DbOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
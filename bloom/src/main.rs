@@ -1,6 +1,250 @@
-mod simple;
-mod slatedb;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use bloom_filter::prefix::PrefixExtractor;
+use bloom_filter::slatedb::{BloomFilterSize, SlatedbBloomFilter};
+use clap::{Parser, Subcommand};
+
+/// Build and query bloom filters stored in files.
+#[derive(Parser)]
+#[command(name = "bloom")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build a filter from a file with one key per line.
+    Build {
+        /// File with one key per line.
+        #[arg(long)]
+        keys: PathBuf,
+        /// Target false positive rate once every key is added.
+        #[arg(long, default_value_t = 0.01)]
+        fpr: f64,
+        /// Where to write the encoded filter.
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Also add the first N bytes of every key, for `query --prefix`.
+        #[arg(long, value_name = "N", conflicts_with = "prefix_delimiter")]
+        prefix_len: Option<usize>,
+        /// Also add every prefix of a key ending with this ASCII character, for
+        /// `query --prefix`.
+        #[arg(long, value_name = "CHAR")]
+        prefix_delimiter: Option<char>,
+    },
+    /// Check keys against a filter, keys are read from stdin when none are given.
+    Query {
+        filter: PathBuf,
+        keys: Vec<String>,
+        /// Ask whether any key starting with each argument may be in the filter.
+        #[arg(long)]
+        prefix: bool,
+    },
+    /// Print the size and fill statistics of a filter.
+    Stats { filter: PathBuf },
+}
 
 fn main() {
-    println!("bloom filter")
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("bloom: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout().lock();
+    match cli.command {
+        Command::Build {
+            keys,
+            fpr,
+            output,
+            prefix_len,
+            prefix_delimiter,
+        } => {
+            if !(fpr > 0.0 && fpr < 1.0) {
+                return Err(format!("--fpr must be in (0, 1), got {}", fpr).into());
+            }
+            if prefix_len == Some(0) {
+                return Err("--prefix-len must be positive".into());
+            }
+            if prefix_delimiter.is_some_and(|delimiter| !delimiter.is_ascii()) {
+                return Err("--prefix-delimiter must be an ASCII character".into());
+            }
+            let extractor = prefix_len
+                .map(PrefixExtractor::FixedLength)
+                .or(prefix_delimiter.map(|delimiter| PrefixExtractor::Delimiter(delimiter as u8)));
+            let reader = io::BufReader::new(fs::File::open(&keys)?);
+            let filter = build(reader, fpr, extractor)?;
+            fs::write(&output, filter.encode())?;
+            writeln!(
+                out,
+                "wrote {} ({} bits, {} probes)",
+                output.display(),
+                filter.num_bits(),
+                filter.num_probes()
+            )?;
+        }
+        Command::Query {
+            filter,
+            keys,
+            prefix,
+        } => {
+            let filter = read_filter(&filter)?;
+            let keys = if keys.is_empty() {
+                io::stdin().lock().lines().collect::<io::Result<Vec<_>>>()?
+            } else {
+                keys
+            };
+            query(&filter, &keys, prefix, &mut out)?;
+        }
+        Command::Stats { filter } => {
+            let filter = read_filter(&filter)?;
+            stats(&filter, &mut out)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_filter(path: &Path) -> Result<SlatedbBloomFilter, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    SlatedbBloomFilter::decode(&bytes).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// Build a filter sized for the keys read from `reader`, one per line, and their prefixes.
+fn build(
+    reader: impl BufRead,
+    fpr: f64,
+    extractor: Option<PrefixExtractor>,
+) -> io::Result<SlatedbBloomFilter> {
+    let keys = reader.lines().collect::<io::Result<Vec<_>>>()?;
+    let num_prefixes = match extractor {
+        None => 0,
+        Some(extractor) => keys
+            .iter()
+            .flat_map(|key| extractor.prefixes(key.as_bytes()))
+            .collect::<HashSet<_>>()
+            .len(),
+    };
+    let size = BloomFilterSize::with_false_positive_rate(keys.len() + num_prefixes, fpr);
+    let mut filter = SlatedbBloomFilter::with_size(size);
+    if let Some(extractor) = extractor {
+        filter = filter.with_prefix_extractor(extractor);
+    }
+    for key in &keys {
+        filter.add_key(key.as_bytes());
+    }
+    Ok(filter)
+}
+
+/// Print `maybe` or `no` for every key, or every prefix with `prefix`.
+fn query(
+    filter: &SlatedbBloomFilter,
+    keys: &[String],
+    prefix: bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for key in keys {
+        let found = if prefix {
+            filter.may_contain_prefix(key.as_bytes())
+        } else {
+            filter.has_key(key.as_bytes())
+        };
+        let answer = if found { "maybe" } else { "no" };
+        writeln!(out, "{}\t{}", key, answer)?;
+    }
+    Ok(())
+}
+
+fn stats(filter: &SlatedbBloomFilter, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "bits: {}", filter.num_bits())?;
+    writeln!(out, "bytes: {}", filter.num_bits().div_ceil(8))?;
+    writeln!(out, "probes: {}", filter.num_probes())?;
+    match filter.prefix_extractor() {
+        None => writeln!(out, "prefixes: none")?,
+        Some(PrefixExtractor::FixedLength(n)) => writeln!(out, "prefixes: first {} bytes", n)?,
+        Some(PrefixExtractor::Delimiter(delimiter)) => {
+            writeln!(out, "prefixes: ending with {:?}", delimiter as char)?
+        }
+    }
+    writeln!(out, "fill ratio: {:.4}", filter.fill_ratio())?;
+    writeln!(out, "estimated keys: {}", filter.estimated_num_keys())?;
+    writeln!(
+        out,
+        "estimated fpr: {:.6}",
+        filter.current_false_positive_rate()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_query() {
+        let keys = "apple\nbanana\norange\n";
+        let filter = build(keys.as_bytes(), 0.01, None).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fruits.bloom");
+        fs::write(&path, filter.encode()).unwrap();
+        let filter = read_filter(&path).unwrap();
+
+        let mut out = Vec::new();
+        let keys = vec!["apple".to_string(), "grape".to_string()];
+        query(&filter, &keys, false, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "apple\tmaybe\ngrape\tno\n");
+    }
+
+    #[test]
+    fn test_query_prefix() {
+        let keys = "user:1:a\nuser:1:b\nuser:2:a\n";
+        let filter = build(
+            keys.as_bytes(),
+            0.01,
+            Some(PrefixExtractor::Delimiter(b':')),
+        )
+        .unwrap();
+        assert_eq!(filter.num_bits(), 10 * 6);
+
+        let mut out = Vec::new();
+        let prefixes = vec!["user:1:".to_string(), "user:3:".to_string()];
+        query(&filter, &prefixes, true, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "user:1:\tmaybe\nuser:3:\tno\n"
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let keys: String = (0..1000).map(|i| format!("key-{}\n", i)).collect();
+        let filter = build(keys.as_bytes(), 0.01, None).unwrap();
+
+        let mut out = Vec::new();
+        stats(&filter, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.starts_with("bits: 10000\nbytes: 1250\nprobes: 6\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("fill ratio: 0.45"), "{}", out);
+    }
+
+    #[test]
+    fn test_read_corrupt_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrupt.bloom");
+        fs::write(&path, b"not a filter").unwrap();
+        let err = read_filter(&path).unwrap_err().to_string();
+        assert!(err.contains("corrupt.bloom"), "{}", err);
+        assert!(err.contains("unknown filter format version"), "{}", err);
+    }
 }
@@ -0,0 +1,58 @@
+//! 4-bit values packed two per byte, the slots of the counting and stable bloom filters.
+
+use crate::slatedb::{set_bit, SlatedbBloomFilter};
+
+/// Largest value a nibble can hold.
+pub(crate) const MAX_NIBBLE: u8 = 0x0F;
+
+/// Array of 4-bit values packed 2 per byte, the low nibble holds the even index.
+pub(crate) struct Nibbles {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Nibbles {
+    /// `len` nibbles, all zero.
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0u8; len.div_ceil(2)],
+            len,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of bytes allocated for the nibbles.
+    pub(crate) fn capacity_bytes(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> u8 {
+        let shift = (index % 2) * 4;
+        (self.bytes[index / 2] >> shift) & MAX_NIBBLE
+    }
+
+    pub(crate) fn set(&mut self, index: usize, value: u8) {
+        debug_assert!(value <= MAX_NIBBLE, "{} does not fit in a nibble", value);
+        let shift = (index % 2) * 4;
+        let byte = &mut self.bytes[index / 2];
+        *byte = (*byte & !(MAX_NIBBLE << shift)) | (value << shift);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.len).map(|index| self.get(index))
+    }
+
+    /// Plain bloom filter with a bit set for every non-zero nibble.
+    pub(crate) fn to_bloom_filter(&self, num_probes: u32, seed: u64) -> SlatedbBloomFilter {
+        let mut buffer = vec![0u8; self.len.div_ceil(8)];
+        for (index, value) in self.iter().enumerate() {
+            if value > 0 {
+                set_bit(index, &mut buffer);
+            }
+        }
+        SlatedbBloomFilter::from_buffer(buffer, self.len, num_probes, seed)
+    }
+}
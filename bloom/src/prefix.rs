@@ -0,0 +1,88 @@
+/// Picks the prefixes of a key that are added to a filter next to the whole key, so a scan can
+/// ask whether any key starting with a prefix may be in the filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixExtractor {
+    /// The first `n` bytes of the key, keys shorter than `n` have no prefix.
+    FixedLength(usize),
+    /// Every prefix that ends with the delimiter byte, `user:42:7` with `:` has the prefixes
+    /// `user:` and `user:42:`.
+    Delimiter(u8),
+}
+
+impl PrefixExtractor {
+    /// Prefixes of `key`, shortest first.
+    pub fn prefixes<'k>(&self, key: &'k [u8]) -> Prefixes<'k> {
+        Prefixes {
+            extractor: *self,
+            key,
+            pos: 0,
+        }
+    }
+}
+
+/// Iterator returned by [`PrefixExtractor::prefixes`].
+pub struct Prefixes<'k> {
+    extractor: PrefixExtractor,
+    key: &'k [u8],
+    /// Where to continue looking, past the end of the key once done.
+    pos: usize,
+}
+
+impl<'k> Iterator for Prefixes<'k> {
+    type Item = &'k [u8];
+
+    fn next(&mut self) -> Option<&'k [u8]> {
+        if self.pos > self.key.len() {
+            return None;
+        }
+        match self.extractor {
+            PrefixExtractor::FixedLength(n) => {
+                self.pos = self.key.len() + 1;
+                self.key.get(..n)
+            }
+            PrefixExtractor::Delimiter(delimiter) => {
+                match self.key[self.pos..].iter().position(|&b| b == delimiter) {
+                    Some(offset) => {
+                        let end = self.pos + offset + 1;
+                        self.pos = end;
+                        Some(&self.key[..end])
+                    }
+                    None => {
+                        self.pos = self.key.len() + 1;
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(extractor: PrefixExtractor, key: &str) -> Vec<&str> {
+        extractor
+            .prefixes(key.as_bytes())
+            .map(|prefix| std::str::from_utf8(prefix).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_length() {
+        let extractor = PrefixExtractor::FixedLength(4);
+        assert_eq!(prefixes(extractor, "user:42"), vec!["user"]);
+        assert_eq!(prefixes(extractor, "user"), vec!["user"]);
+        assert!(prefixes(extractor, "usr").is_empty());
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let extractor = PrefixExtractor::Delimiter(b':');
+        assert_eq!(prefixes(extractor, "user:42:7"), vec!["user:", "user:42:"]);
+        assert_eq!(prefixes(extractor, "user:42:"), vec!["user:", "user:42:"]);
+        assert_eq!(prefixes(extractor, "::"), vec![":", "::"]);
+        assert!(prefixes(extractor, "user").is_empty());
+        assert!(prefixes(extractor, "").is_empty());
+    }
+}
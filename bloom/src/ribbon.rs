@@ -0,0 +1,349 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{ensure_consumed, ensure_remaining, verify_checksum, FilterDecodeError};
+use crate::hash::{hash64, reduce, splitmix64, DEFAULT_SEED, HASH_XXH64};
+
+/// Version of the encoded filter layout, bumped on any incompatible change.
+const FORMAT_VERSION: u8 = 1;
+/// version, hash id, result bits, seed, num_keys, num_starts
+const HEADER_LEN: usize = 1 + 1 + 1 + 8 + 8 + 8;
+/// Bits in a key's coefficient row, one u64.
+const RIBBON_WIDTH: usize = 64;
+/// Extra slots over the number of keys to start with. Banding fails more often with less,
+/// and more so the more keys there are, so every failed build adds `SLOTS_OVERHEAD_STEP`.
+/// 1M keys end up with ~10% extra slots, 4M with ~12%.
+const SLOTS_OVERHEAD: f64 = 0.08;
+const SLOTS_OVERHEAD_STEP: f64 = 0.01;
+
+/// Standard ribbon filter, built once from a complete key set.
+///
+/// Each key is one linear equation over GF(2): a random 64-bit coefficient row starting at a
+/// random slot, xor-ed with the `result_bits`-bit solution rows it covers, must equal the
+/// key's fingerprint. Building solves the system by banding (Gaussian elimination on rows
+/// sorted by their start, done on the fly) and back-substitution, and a lookup xors the
+/// solution rows of the key and compares. A key that was not in the set matches with
+/// probability `2^-result_bits` at ~`1.1 * result_bits` bits per key, where a bloom filter
+/// needs `1.44 * result_bits`, about 25% less space at the same rate. No key can be added
+/// after the filter is built, which suits immutable sorted runs.
+/// https://arxiv.org/abs/2103.02515
+pub struct RibbonFilter {
+    /// Solution rows interleaved by blocks of 64 slots: word `block * result_bits + bit`
+    /// holds bit `bit` of the solution rows `block * 64..(block + 1) * 64`.
+    words: Vec<u64>,
+    result_bits: u32,
+    /// Number of slots a coefficient row can start at.
+    num_starts: usize,
+    num_keys: usize,
+    seed: u64,
+}
+
+impl RibbonFilter {
+    /// Build a filter holding exactly `keys` with `result_bits` bits of fingerprint per slot,
+    /// duplicates are ignored.
+    ///
+    /// Panics unless `result_bits` is in `1..=32`.
+    pub fn build<K: AsRef<[u8]>>(keys: &[K], result_bits: u32) -> Self {
+        assert!(
+            (1..=32).contains(&result_bits),
+            "result_bits must be in 1..=32, got {}",
+            result_bits
+        );
+        let mut overhead = SLOTS_OVERHEAD;
+        let mut seed = DEFAULT_SEED;
+        loop {
+            let num_starts = (keys.len() as f64 * (1.0 + overhead)).ceil() as usize + 1;
+            let mut hashes: Vec<u64> = keys.iter().map(|key| hash64(key.as_ref(), seed)).collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+            if let Some(words) = Self::solve(&hashes, num_starts, result_bits) {
+                return Self {
+                    words,
+                    result_bits,
+                    num_starts,
+                    num_keys: hashes.len(),
+                    seed,
+                };
+            }
+            // two keys' equations contradict each other, retry with another seed and more
+            // room.
+            seed = seed.wrapping_add(1);
+            overhead += SLOTS_OVERHEAD_STEP;
+        }
+    }
+
+    /// Build a filter holding exactly `keys` whose false positive rate is at most `fpr`.
+    pub fn build_with_false_positive_rate<K: AsRef<[u8]>>(keys: &[K], fpr: f64) -> Self {
+        assert!(fpr > 0.0 && fpr < 1.0, "fpr must be in (0, 1), got {}", fpr);
+        let result_bits = (-fpr.log2()).ceil().clamp(1.0, 32.0) as u32;
+        Self::build(keys, result_bits)
+    }
+
+    /// Number of distinct keys the filter was built from.
+    pub fn len(&self) -> usize {
+        self.num_keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_keys == 0
+    }
+
+    pub fn result_bits(&self) -> u32 {
+        self.result_bits
+    }
+
+    /// False positive rate of the filter, `2^-result_bits`.
+    pub fn false_positive_rate(&self) -> f64 {
+        (-(self.result_bits as f64)).exp2()
+    }
+
+    /// Number of bytes used by the filter, including the solution.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.words.capacity() * std::mem::size_of::<u64>()
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        let (start, coefficients, result) = self.equation(hash64(key, self.seed));
+        let block = start / RIBBON_WIDTH;
+        let offset = start % RIBBON_WIDTH;
+        let r = self.result_bits as usize;
+        let low = &self.words[block * r..(block + 1) * r];
+        let mut found = 0u32;
+        if offset == 0 {
+            for (bit, word) in low.iter().enumerate() {
+                found |= ((word & coefficients).count_ones() & 1) << bit;
+            }
+        } else {
+            let high = &self.words[(block + 1) * r..(block + 2) * r];
+            let low_mask = coefficients << offset;
+            let high_mask = coefficients >> (RIBBON_WIDTH - offset);
+            for (bit, (low, high)) in low.iter().zip(high).enumerate() {
+                let parity = (low & low_mask).count_ones() ^ (high & high_mask).count_ones();
+                found |= (parity & 1) << bit;
+            }
+        }
+        found == result
+    }
+
+    /// Decode a filter written by [`RibbonFilter::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<RibbonFilter, FilterDecodeError> {
+        let body = verify_checksum(encoded)?;
+        let mut buf = body;
+        ensure_remaining(buf, HEADER_LEN, 0)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(FilterDecodeError::UnknownVersion(version));
+        }
+        let hash_algorithm = buf.get_u8();
+        if hash_algorithm != HASH_XXH64 {
+            return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+        }
+        let result_bits = buf.get_u8() as u32;
+        let seed = buf.get_u64();
+        let num_keys = usize::try_from(buf.get_u64())
+            .map_err(|_| FilterDecodeError::InvalidHeader("num_keys does not fit in memory"))?;
+        let num_starts = usize::try_from(buf.get_u64())
+            .map_err(|_| FilterDecodeError::InvalidHeader("num_starts does not fit in memory"))?;
+        if !(1..=32).contains(&result_bits) {
+            return Err(FilterDecodeError::InvalidHeader(
+                "result_bits must be in 1..=32",
+            ));
+        }
+        if num_starts == 0 {
+            return Err(FilterDecodeError::InvalidHeader("num_starts is zero"));
+        }
+        if num_starts.checked_add(RIBBON_WIDTH - 1).is_none() {
+            return Err(FilterDecodeError::InvalidHeader("too many slots"));
+        }
+        let num_words = num_blocks(num_starts)
+            .checked_mul(result_bits as usize)
+            .ok_or(FilterDecodeError::InvalidHeader("too many slots"))?;
+        ensure_remaining(buf, num_words.saturating_mul(8), HEADER_LEN)?;
+        let words = (0..num_words).map(|_| buf.get_u64()).collect();
+        ensure_consumed(buf, body)?;
+        Ok(Self {
+            words,
+            result_bits,
+            num_starts,
+            num_keys,
+            seed,
+        })
+    }
+
+    /// Encode the filter.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | result_bits (u8) | seed (u64) | num_keys (u64) |
+    /// | num_starts (u64) | solution (ceil((num_starts + 63) / 64) * result_bits u64) |
+    /// | crc32 of everything before it (u32) |
+    /// ```
+    pub fn encode(&self) -> Bytes {
+        let mut encoded = BytesMut::with_capacity(HEADER_LEN + self.words.len() * 8 + 4);
+        encoded.put_u8(FORMAT_VERSION);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u8(self.result_bits as u8);
+        encoded.put_u64(self.seed);
+        encoded.put_u64(self.num_keys as u64);
+        encoded.put_u64(self.num_starts as u64);
+        for word in &self.words {
+            encoded.put_u64(*word);
+        }
+        encoded.put_u32(crc32fast::hash(&encoded));
+        encoded.freeze()
+    }
+
+    fn equation(&self, hash: u64) -> (usize, u64, u32) {
+        equation(hash, self.num_starts, self.result_bits)
+    }
+
+    /// Solve the equations of distinct `hashes`, or None if two of them contradict each
+    /// other and the filter must be rebuilt with another seed.
+    fn solve(hashes: &[u64], num_starts: usize, result_bits: u32) -> Option<Vec<u64>> {
+        let num_slots = num_blocks(num_starts) * RIBBON_WIDTH;
+        // banding: row `i` is empty or holds an equation whose coefficients start at `i`,
+        // that is with bit 0 set. Each new equation is reduced by the rows in its way until
+        // it lands on an empty row.
+        let mut coefficients = vec![0u64; num_slots];
+        let mut results = vec![0u32; num_slots];
+        for &hash in hashes {
+            let (mut start, mut row, mut result) = equation(hash, num_starts, result_bits);
+            loop {
+                if coefficients[start] == 0 {
+                    coefficients[start] = row;
+                    results[start] = result;
+                    break;
+                }
+                row ^= coefficients[start];
+                result ^= results[start];
+                if row == 0 {
+                    // distinct hashes can still make the same equation, that is fine as
+                    // long as the results agree too.
+                    if result != 0 {
+                        return None;
+                    }
+                    break;
+                }
+                let shift = row.trailing_zeros();
+                start += shift as usize;
+                row >>= shift;
+            }
+        }
+
+        // back-substitution from the last row, each solution row is fixed by the rows after
+        // it. Rows without an equation are free and left zero.
+        let mut solution = vec![0u32; num_slots];
+        for i in (0..num_slots).rev() {
+            let mut row = coefficients[i] >> 1;
+            let mut value = results[i];
+            while row != 0 {
+                let j = row.trailing_zeros() as usize + 1;
+                value ^= solution[i + j];
+                row &= row - 1;
+            }
+            solution[i] = value;
+        }
+
+        let r = result_bits as usize;
+        let mut words = vec![0u64; num_blocks(num_starts) * r];
+        for (slot, value) in solution.into_iter().enumerate() {
+            let (block, offset) = (slot / RIBBON_WIDTH, slot % RIBBON_WIDTH);
+            for bit in 0..r {
+                words[block * r + bit] |= ((value >> bit) as u64 & 1) << offset;
+            }
+        }
+        Some(words)
+    }
+}
+
+/// Number of 64-slot blocks covering every row a coefficient can reach.
+fn num_blocks(num_starts: usize) -> usize {
+    (num_starts + RIBBON_WIDTH - 1).div_ceil(RIBBON_WIDTH)
+}
+
+/// Start slot, coefficient row with bit 0 set, and fingerprint of the key with `hash`.
+fn equation(hash: u64, num_starts: usize, result_bits: u32) -> (usize, u64, u32) {
+    let start = reduce((hash >> 32) as u32, num_starts);
+    // remix, so the coefficients are not a shift of the start bits.
+    let mut state = hash;
+    let mixed = splitmix64(&mut state);
+    let result = (hash as u32) & (u32::MAX >> (32 - result_bits));
+    (start, mixed | 1, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ribbon_filter_works() {
+        let filter = RibbonFilter::build(&["apple", "banana", "orange"], 16);
+        assert!(filter.has_key(b"apple"));
+        assert!(filter.has_key(b"banana"));
+        assert!(filter.has_key(b"orange"));
+        assert!(!filter.has_key(b"grape"));
+        assert!(!filter.has_key(b"mango"));
+        assert!(!filter.has_key(b"kiwi"));
+    }
+
+    #[test]
+    fn test_duplicate_and_empty_key_sets() {
+        let filter = RibbonFilter::build(&["apple", "apple", "banana"], 8);
+        assert_eq!(filter.len(), 2);
+        assert!(filter.has_key(b"apple"));
+
+        let empty: [&[u8]; 0] = [];
+        let filter = RibbonFilter::build(&empty, 8);
+        assert!(filter.is_empty());
+        assert!(!filter.has_key(b"apple"));
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let keys: Vec<String> = (0..100_000).map(|i| format!("key-{}", i)).collect();
+        let filter = RibbonFilter::build_with_false_positive_rate(&keys, 0.01);
+        assert_eq!(filter.result_bits(), 7);
+        for key in &keys {
+            assert!(filter.has_key(key.as_bytes()));
+        }
+        let false_positives = (0..100_000)
+            .filter(|i| filter.has_key(format!("absent-{}", i).as_bytes()))
+            .count();
+        // 1/128 expected, ~780 of 100_000.
+        assert!((650..920).contains(&false_positives), "{}", false_positives);
+        // ~7.6 bits per key, against ~10 for a bloom filter at 1%.
+        let bits_per_key = (filter.encode().len() * 8) as f64 / keys.len() as f64;
+        assert!(bits_per_key < 7.7, "{}", bits_per_key);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let filter = RibbonFilter::build(&keys, 10);
+        let encoded = filter.encode();
+        let decoded = RibbonFilter::decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), 1000);
+        for key in &keys {
+            assert!(decoded.has_key(key.as_bytes()));
+        }
+        assert_eq!(decoded.encode(), encoded);
+
+        for len in 0..encoded.len() {
+            assert!(RibbonFilter::decode(&encoded[..len]).is_err());
+        }
+        let mut corrupted = encoded.to_vec();
+        corrupted[HEADER_LEN + 20] ^= 1;
+        assert!(matches!(
+            RibbonFilter::decode(&corrupted),
+            Err(FilterDecodeError::ChecksumMismatch { .. })
+        ));
+
+        // a well formed checksum around a num_starts that overflows the block count.
+        let mut overflowing = encoded[..HEADER_LEN].to_vec();
+        overflowing[HEADER_LEN - 8..].copy_from_slice(&(u64::MAX - 10).to_be_bytes());
+        overflowing.extend_from_slice(&crc32fast::hash(&overflowing).to_be_bytes());
+        assert_eq!(
+            RibbonFilter::decode(&overflowing).err(),
+            Some(FilterDecodeError::InvalidHeader("too many slots"))
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{ensure_consumed, ensure_remaining, verify_checksum, FilterDecodeError};
+use crate::filter::Filter;
+use crate::hash::{DEFAULT_SEED, HASH_XXH64};
+use crate::slatedb::{BloomFilterSize, SlatedbBloomFilter};
+
+/// Version of the encoded filter layout, bumped on any incompatible change.
+const FORMAT_VERSION: u8 = 1;
+/// version, hash id, initial_capacity, fpr, seed, num_layers
+const HEADER_LEN: usize = 1 + 1 + 8 + 8 + 8 + 4;
+/// capacity, len, filter_len
+const LAYER_HEADER_LEN: usize = 8 + 8 + 4;
+
+/// Each layer holds this many times more keys than the previous one.
+const GROWTH_FACTOR: usize = 2;
+/// Each layer targets this fraction of the previous layer's false positive rate.
+const TIGHTENING_RATIO: f64 = 0.5;
+
+/// Scalable bloom filter, a chain of bloom filters that grows as keys are added.
+///
+/// Once the newest layer holds as many keys as it was sized for, a new layer with
+/// `GROWTH_FACTOR` times the capacity and `TIGHTENING_RATIO` times the false positive rate
+/// is appended. Layer `i` targets `fpr * (1 - r) * r^i`, so the compound false positive rate
+/// of all layers stays below `fpr` no matter how many keys are added.
+/// https://gsd.di.uminho.pt/members/cbm/ps/dbloom.pdf
+pub struct ScalableBloomFilter {
+    layers: Vec<Layer>,
+    initial_capacity: usize,
+    /// Upper bound of the compound false positive rate.
+    fpr: f64,
+    seed: u64,
+}
+
+struct Layer {
+    filter: SlatedbBloomFilter,
+    /// Number of keys the layer was sized for.
+    capacity: usize,
+    /// Number of keys added to the layer.
+    len: usize,
+}
+
+impl ScalableBloomFilter {
+    pub fn new(initial_capacity: usize, fpr: f64) -> Self {
+        Self::new_with_seed(initial_capacity, fpr, DEFAULT_SEED)
+    }
+
+    pub fn new_with_seed(initial_capacity: usize, fpr: f64, seed: u64) -> Self {
+        assert!(fpr > 0.0 && fpr < 1.0, "fpr must be in (0, 1), got {}", fpr);
+        let mut filter = Self {
+            layers: Vec::new(),
+            initial_capacity: initial_capacity.max(1),
+            fpr,
+            seed,
+        };
+        filter.add_layer();
+        filter
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        // skip keys already present, they would use up capacity without adding bits.
+        if self.has_key(key) {
+            return;
+        }
+        let layer = self.layers.last().expect("filter has at least one layer");
+        if layer.len >= layer.capacity {
+            self.add_layer();
+        }
+        let layer = self
+            .layers
+            .last_mut()
+            .expect("filter has at least one layer");
+        layer.filter.add_key(key);
+        layer.len += 1;
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        // newest layers hold the most keys, check them first.
+        self.layers
+            .iter()
+            .rev()
+            .any(|layer| layer.filter.has_key(key))
+    }
+
+    /// Number of distinct keys added, keys that were false positives when added are not
+    /// counted.
+    pub fn len(&self) -> usize {
+        self.layers.iter().map(|layer| layer.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Fraction of bits set in the newest layer, the one keys are added to.
+    pub fn fill_ratio(&self) -> f64 {
+        let layer = self.layers.last().expect("filter has at least one layer");
+        layer.filter.fill_ratio()
+    }
+
+    /// Theoretical false positive rate of all layers at their current number of keys.
+    pub fn false_positive_rate(&self) -> f64 {
+        let all_negative: f64 = self
+            .layers
+            .iter()
+            .map(|layer| 1.0 - layer.filter.false_positive_rate(layer.len))
+            .product();
+        1.0 - all_negative
+    }
+
+    /// Number of bytes used by the filter, including the bit buffers of all layers.
+    pub fn memory_size(&self) -> usize {
+        let layers: usize = self
+            .layers
+            .iter()
+            .map(|layer| layer.filter.memory_size())
+            .sum();
+        std::mem::size_of::<Self>() + layers
+    }
+
+    /// Decode a filter written by [`ScalableBloomFilter::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<ScalableBloomFilter, FilterDecodeError> {
+        let body = verify_checksum(encoded)?;
+        let mut buf = body;
+        ensure_remaining(buf, HEADER_LEN, 0)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(FilterDecodeError::UnknownVersion(version));
+        }
+        let hash_algorithm = buf.get_u8();
+        if hash_algorithm != HASH_XXH64 {
+            return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+        }
+        let initial_capacity = usize::try_from(buf.get_u64()).map_err(|_| {
+            FilterDecodeError::InvalidHeader("initial_capacity does not fit in memory")
+        })?;
+        let fpr = buf.get_f64();
+        let seed = buf.get_u64();
+        let num_layers = buf.get_u32();
+        if initial_capacity == 0 {
+            return Err(FilterDecodeError::InvalidHeader("initial_capacity is zero"));
+        }
+        // also rejects NaN.
+        if !(fpr > 0.0 && fpr < 1.0) {
+            return Err(FilterDecodeError::InvalidHeader("fpr is not in (0, 1)"));
+        }
+        if num_layers == 0 {
+            return Err(FilterDecodeError::InvalidHeader(
+                "scalable filter has no layers",
+            ));
+        }
+        // the next layer added must still have a capacity that fits.
+        layer_capacity(initial_capacity, num_layers).ok_or(FilterDecodeError::InvalidHeader(
+            "too many layers for initial_capacity",
+        ))?;
+        let mut layers = Vec::new();
+        for i in 0..num_layers {
+            ensure_remaining(buf, LAYER_HEADER_LEN, body.len() - buf.len())?;
+            let capacity = buf.get_u64();
+            let len = buf.get_u64();
+            let filter_len = buf.get_u32() as usize;
+            ensure_remaining(buf, filter_len, body.len() - buf.len())?;
+            let filter = SlatedbBloomFilter::decode(&buf[..filter_len])?;
+            buf.advance(filter_len);
+            if layer_capacity(initial_capacity, i).map(|capacity| capacity as u64) != Some(capacity)
+            {
+                return Err(FilterDecodeError::InvalidHeader(
+                    "layer capacity does not match initial_capacity",
+                ));
+            }
+            let capacity = capacity as usize;
+            if len > capacity as u64 {
+                return Err(FilterDecodeError::InvalidHeader(
+                    "layer holds more keys than its capacity",
+                ));
+            }
+            // every layer targets under 50% false positives, which takes more than a bit per
+            // key, so the encoded bits bound the capacity, and the size of the next layer.
+            if filter.num_bits() < capacity {
+                return Err(FilterDecodeError::InvalidHeader(
+                    "layer has fewer bits than its capacity",
+                ));
+            }
+            if filter.seed() != seed {
+                return Err(FilterDecodeError::InvalidHeader(
+                    "layer seed does not match the filter seed",
+                ));
+            }
+            layers.push(Layer {
+                filter,
+                capacity,
+                len: len as usize,
+            });
+        }
+        ensure_consumed(buf, body)?;
+        Ok(Self {
+            layers,
+            initial_capacity,
+            fpr,
+            seed,
+        })
+    }
+
+    /// Encode the filter and all of its layers.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | initial_capacity (u64) | fpr (f64) | seed (u64) |
+    /// | num_layers (u32) | layer ... | crc32 of everything before it (u32) |
+    /// layer: | capacity (u64) | len (u64) | filter_len (u32) | SlatedbBloomFilter::encode |
+    /// ```
+    /// Layer `i` must have a capacity of `initial_capacity * GROWTH_FACTOR^i` and the filter
+    /// seed.
+    pub fn encode(&self) -> Bytes {
+        let mut encoded = BytesMut::new();
+        encoded.put_u8(FORMAT_VERSION);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u64(self.initial_capacity as u64);
+        encoded.put_f64(self.fpr);
+        encoded.put_u64(self.seed);
+        encoded.put_u32(self.layers.len() as u32);
+        for layer in &self.layers {
+            let filter = layer.filter.encode();
+            encoded.put_u64(layer.capacity as u64);
+            encoded.put_u64(layer.len as u64);
+            encoded.put_u32(filter.len() as u32);
+            encoded.put_slice(&filter);
+        }
+        encoded.put_u32(crc32fast::hash(&encoded));
+        encoded.freeze()
+    }
+
+    fn add_layer(&mut self) {
+        let i = self.layers.len() as i32;
+        let capacity = layer_capacity(self.initial_capacity, i as u32).expect("capacity overflow");
+        let fpr = self.fpr * (1.0 - TIGHTENING_RATIO) * TIGHTENING_RATIO.powi(i);
+        let size = BloomFilterSize::with_false_positive_rate(capacity, fpr);
+        self.layers.push(Layer {
+            filter: SlatedbBloomFilter::new_with_seed(size.num_bits, size.num_probes, self.seed),
+            capacity,
+            len: 0,
+        });
+    }
+}
+
+/// Number of keys layer `i` is sized for, None if it overflows.
+fn layer_capacity(initial_capacity: usize, i: u32) -> Option<usize> {
+    GROWTH_FACTOR
+        .checked_pow(i)
+        .and_then(|growth| growth.checked_mul(initial_capacity))
+}
+
+impl Filter for ScalableBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    fn estimated_len(&self) -> usize {
+        self.len()
+    }
+
+    fn encode(&self) -> Bytes {
+        ScalableBloomFilter::encode(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_works() {
+        let mut filter = ScalableBloomFilter::new(100, 0.01);
+        filter.add_key(b"apple");
+        filter.add_key(b"banana");
+        filter.add_key(b"orange");
+
+        assert!(filter.has_key(b"apple"));
+        assert!(!filter.has_key(b"grape"));
+        assert_eq!(filter.len(), 3);
+        assert_eq!(filter.num_layers(), 1);
+    }
+
+    #[test]
+    fn test_grows_and_keeps_fpr_bounded() {
+        let fpr = 0.01;
+        let mut filter = ScalableBloomFilter::new(100, fpr);
+        for i in 0..10_000 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        // 100 + 200 + ... + 3200 < 10_000 <= 100 + 200 + ... + 6400
+        assert_eq!(filter.num_layers(), 7);
+        assert!(filter.fill_ratio() > 0.0 && filter.fill_ratio() < 0.5);
+        assert!(filter.false_positive_rate() < fpr);
+
+        for i in 0..10_000 {
+            assert!(filter.has_key(format!("key-{}", i).as_bytes()));
+        }
+        let false_positives = (0..10_000)
+            .filter(|i| filter.has_key(format!("absent-{}", i).as_bytes()))
+            .count();
+        // allow for sampling noise around the 1% bound.
+        assert!(false_positives < 150, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let mut filter = ScalableBloomFilter::new_with_seed(10, 0.01, 5);
+        for i in 0..100 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let decoded = ScalableBloomFilter::decode(&filter.encode()).unwrap();
+        assert_eq!(decoded.num_layers(), filter.num_layers());
+        assert_eq!(decoded.len(), filter.len());
+        for i in 0..100 {
+            assert!(decoded.has_key(format!("key-{}", i).as_bytes()));
+        }
+        assert_eq!(decoded.encode(), filter.encode());
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let mut filter = ScalableBloomFilter::new(10, 0.01);
+        for i in 0..100 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let encoded = filter.encode();
+        for len in 0..encoded.len() {
+            assert!(ScalableBloomFilter::decode(&encoded[..len]).is_err());
+        }
+    }
+
+    /// `body` with a well formed checksum appended.
+    fn with_checksum(body: &[u8]) -> Vec<u8> {
+        let mut encoded = body.to_vec();
+        encoded.extend_from_slice(&crc32fast::hash(body).to_be_bytes());
+        encoded
+    }
+
+    #[test]
+    fn test_decode_rejects_corruption() {
+        let filter = ScalableBloomFilter::new(10, 0.01);
+        let mut corrupted = filter.encode().to_vec();
+        // fpr 0.01 to 0.5 still is a valid rate, only the checksum catches it.
+        corrupted[10..18].copy_from_slice(&0.5f64.to_be_bytes());
+        assert!(matches!(
+            ScalableBloomFilter::decode(&corrupted),
+            Err(FilterDecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_layers() {
+        let mut filter = ScalableBloomFilter::new_with_seed(10, 0.01, 5);
+        for i in 0..30 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let encoded = filter.encode();
+        let body = &encoded[..encoded.len() - 4];
+        let with_layer = |capacity: u64, len: u64| {
+            let mut damaged = body.to_vec();
+            damaged[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&capacity.to_be_bytes());
+            damaged[HEADER_LEN + 8..HEADER_LEN + 16].copy_from_slice(&len.to_be_bytes());
+            ScalableBloomFilter::decode(&with_checksum(&damaged)).err()
+        };
+        assert_eq!(with_layer(10, 10), None);
+        assert_eq!(
+            with_layer(10, 11),
+            Some(FilterDecodeError::InvalidHeader(
+                "layer holds more keys than its capacity"
+            ))
+        );
+        assert_eq!(
+            with_layer(10, u64::MAX),
+            Some(FilterDecodeError::InvalidHeader(
+                "layer holds more keys than its capacity"
+            ))
+        );
+        assert_eq!(
+            with_layer(20, 10),
+            Some(FilterDecodeError::InvalidHeader(
+                "layer capacity does not match initial_capacity"
+            ))
+        );
+
+        // a huge initial_capacity needs as many encoded bits.
+        let mut huge = body.to_vec();
+        huge[2..10].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        huge[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        assert_eq!(
+            ScalableBloomFilter::decode(&with_checksum(&huge)).err(),
+            Some(FilterDecodeError::InvalidHeader(
+                "layer has fewer bits than its capacity"
+            ))
+        );
+
+        let other_seed = ScalableBloomFilter::new_with_seed(10, 0.01, 6).encode();
+        let mut mixed = body[..HEADER_LEN].to_vec();
+        mixed[26..30].copy_from_slice(&1u32.to_be_bytes());
+        mixed.extend_from_slice(&other_seed[HEADER_LEN..other_seed.len() - 4]);
+        assert_eq!(
+            ScalableBloomFilter::decode(&with_checksum(&mixed)).err(),
+            Some(FilterDecodeError::InvalidHeader(
+                "layer seed does not match the filter seed"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_header() {
+        let filter = ScalableBloomFilter::new(10, 0.01);
+        let encoded = filter.encode();
+        let with_header = |initial_capacity: u64, fpr: f64, num_layers: u32| {
+            let mut damaged = encoded[..encoded.len() - 4].to_vec();
+            damaged[2..10].copy_from_slice(&initial_capacity.to_be_bytes());
+            damaged[10..18].copy_from_slice(&fpr.to_be_bytes());
+            damaged[26..30].copy_from_slice(&num_layers.to_be_bytes());
+            ScalableBloomFilter::decode(&with_checksum(&damaged)).err()
+        };
+        assert!(with_header(10, 0.01, 1).is_none());
+        for (initial_capacity, fpr, num_layers, reason) in [
+            (0, 0.01, 1, "initial_capacity is zero"),
+            (10, 0.0, 1, "fpr is not in (0, 1)"),
+            (10, 1.0, 1, "fpr is not in (0, 1)"),
+            (10, f64::NAN, 1, "fpr is not in (0, 1)"),
+            (10, 0.01, 64, "too many layers for initial_capacity"),
+            (1 << 62, 0.01, 2, "too many layers for initial_capacity"),
+        ] {
+            assert_eq!(
+                with_header(initial_capacity, fpr, num_layers),
+                Some(FilterDecodeError::InvalidHeader(reason))
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys_do_not_use_capacity() {
+        let mut filter = ScalableBloomFilter::new(10, 0.01);
+        for _ in 0..100 {
+            filter.add_key(b"apple");
+        }
+        assert_eq!(filter.len(), 1);
+        assert_eq!(filter.num_layers(), 1);
+    }
+}
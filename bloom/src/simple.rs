@@ -1,57 +1,115 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
+use bytes::Bytes;
+
+use crate::filter::Filter;
+use crate::hash::{probes, DEFAULT_SEED};
+use crate::slatedb::{
+    check_bit, count_set_bits, encode_bits, estimate_num_keys, false_positive_rate, set_bit,
+    BitEncoding, BloomFilterSize,
+};
 
 /// BloomFilter gives a FIRM no or a PROBABLY yes using probabilistic data structure.
-struct SimpleBloomFilter {
-    bit_set: Vec<bool>,
+pub struct SimpleBloomFilter {
+    /// Bits packed 8 per byte, addressed with `set_bit`/`check_bit`.
+    buffer: Vec<u8>,
+    /// Number of addressable bits, `buffer` is rounded up to a whole byte.
+    num_bits: usize,
     /// Number of hash functions used to determine the bit to check for key existence.
     /// https://en.wikipedia.org/wiki/Bloom_filter#Optimal_number_of_hash_functions.
     num_probes: u32,
+    /// Seed of the key hash, filters only agree on a key if they share the seed.
+    seed: u64,
 }
 
 impl SimpleBloomFilter {
     pub fn new(size: usize, num_probes: u32) -> Self {
+        Self::new_with_seed(size, num_probes, DEFAULT_SEED)
+    }
+
+    /// Panics on a size of zero.
+    pub fn new_with_seed(size: usize, num_probes: u32, seed: u64) -> Self {
+        assert!(size > 0, "size must be positive");
         Self {
-            bit_set: vec![false; size],
+            buffer: vec![0u8; size.div_ceil(8)],
+            num_bits: size,
             num_probes,
+            seed,
         }
     }
 
+    /// Create a filter with bit count and probe count derived from `size`.
+    pub fn with_size(size: BloomFilterSize) -> Self {
+        Self::new(size.num_bits, size.num_probes)
+    }
+
+    /// Theoretical false positive rate of the filter once it holds `num_keys` keys.
+    pub fn false_positive_rate(&self, num_keys: usize) -> f64 {
+        false_positive_rate(self.num_bits, num_keys, self.num_probes)
+    }
+
+    /// Number of bytes used by the filter, including the packed bit buffer.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.buffer.capacity()
+    }
+
+    /// Encode the filter in the same layout as `SlatedbBloomFilter::encode`, so it can be
+    /// read back with `SlatedbBloomFilter::decode`.
+    pub fn encode(&self) -> Bytes {
+        encode_bits(
+            &self.buffer,
+            self.num_bits,
+            self.num_probes,
+            self.seed,
+            None,
+            BitEncoding::Bitmap,
+        )
+    }
+
     pub fn add_key(&mut self, key: &[u8]) {
         let indices = self.hash_key(key);
         for index in indices {
-            self.bit_set[index] = true;
+            set_bit(index, &mut self.buffer);
         }
     }
 
     pub fn has_key(&self, key: &[u8]) -> bool {
         let indices = self.hash_key(key);
         for index in indices {
-            if !self.bit_set[index] {
+            if !check_bit(index, &self.buffer) {
                 return false;
             }
         }
         true // if all bits are 1, key may be in set (false positive possible)
     }
 
-    pub fn hash_key(&self, key: &[u8]) -> Vec<usize> {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let hash = hasher.finish();
-        let size = self.bit_set.len() as u64;
-        let mut result = Vec::with_capacity(self.num_probes as usize);
-        // double hashing scheme to generate multiple unique indices from a single hash value.
-        let h1 = (hash << 32) >> 32;
-        let h2 = hash >> 32;
-        for i in 0..self.num_probes {
-            let index = ((h1.wrapping_add(i as u64 * h2)) % size) as usize;
-            result.push(index);
-        }
-        result
+    /// Positions probed for `key`, computed lazily so a lookup can stop at the first unset one.
+    pub fn hash_key(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        probes(key, self.seed, self.num_probes, self.num_bits)
+    }
+}
+
+impl Filter for SimpleBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    fn estimated_len(&self) -> usize {
+        estimate_num_keys(self.num_bits, count_set_bits(&self.buffer), self.num_probes)
+    }
+
+    fn encode(&self) -> Bytes {
+        SimpleBloomFilter::encode(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -67,4 +125,61 @@ mod tests {
         assert!(!filter.has_key("mango".as_bytes()));
         assert!(!filter.has_key("kiwi".as_bytes()));
     }
+
+    #[test]
+    fn test_memory_size_is_one_bit_per_slot() {
+        let filter = SimpleBloomFilter::new(80_000, 3);
+        assert_eq!(filter.buffer.len(), 10_000);
+        assert!(filter.memory_size() < 80_000 / 8 + 64);
+    }
+
+    #[test]
+    fn test_sized_from_false_positive_rate() {
+        let size = BloomFilterSize::with_false_positive_rate(1000, 0.01);
+        let filter = SimpleBloomFilter::with_size(size);
+        assert_eq!(filter.num_bits, size.num_bits);
+        assert_eq!(filter.num_probes, size.num_probes);
+        assert!(filter.false_positive_rate(1000) <= 0.01);
+    }
+
+    #[test]
+    fn test_hash_key_golden_vectors() {
+        // Same positions as the slatedb filter, both hash with xxHash64.
+        let filter = SimpleBloomFilter::new(1000, 3);
+        assert_eq!(
+            filter.hash_key(b"apple").collect::<Vec<_>>(),
+            vec![543, 392, 241]
+        );
+        let seeded = SimpleBloomFilter::new_with_seed(1000, 3, 42);
+        assert_eq!(
+            seeded.hash_key(b"apple").collect::<Vec<_>>(),
+            vec![159, 576, 993]
+        );
+    }
+
+    #[test]
+    fn test_encode_is_readable_by_slatedb_filter() {
+        let mut filter = SimpleBloomFilter::new(1000, 3);
+        filter.add_key(b"apple");
+        let decoded = crate::slatedb::SlatedbBloomFilter::decode(&filter.encode()).unwrap();
+        assert!(decoded.has_key(b"apple"));
+        assert_eq!(decoded.encode(), filter.encode());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_inserted_keys_are_always_found(
+            keys in vec(vec(any::<u8>(), 0..32), 1..200),
+            bits_per_key in 1u32..16,
+        ) {
+            let size = BloomFilterSize::with_bits_per_key(keys.len(), bits_per_key);
+            let mut filter = SimpleBloomFilter::with_size(size);
+            for key in &keys {
+                filter.add_key(key);
+            }
+            for key in &keys {
+                prop_assert!(filter.has_key(key));
+            }
+        }
+    }
 }
@@ -1,74 +1,802 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fmt;
+use std::ops::{BitAndAssign, BitOrAssign};
 
-use bytes::Bytes;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::{ensure_remaining, verify_checksum, FilterDecodeError, IncompatibleFilterError};
+use crate::filter::Filter;
+use crate::golomb;
+use crate::hash::{hash64, probes, probes_from_hash, DEFAULT_SEED, HASH_XXH64};
+use crate::prefix::PrefixExtractor;
+
+/// Version of the encoded filter layout, bumped on any incompatible change.
+/// Version 2 added the prefix extractor, version 1 filters decode without one.
+/// Version 3 added the bit encoding, older filters store a bitmap.
+const FORMAT_VERSION: u8 = 3;
+/// version, hash id, num_probes, seed, num_bits
+const HEADER_LEN_V1: usize = 1 + 1 + 4 + 8 + 8;
+/// version 1 header, prefix extractor kind, prefix extractor parameter
+const HEADER_LEN_V2: usize = HEADER_LEN_V1 + 1 + 8;
+/// version 2 header, bit encoding
+const HEADER_LEN: usize = HEADER_LEN_V2 + 1;
+const CHECKSUM_LEN: usize = 4;
+/// Ids of the bit encodings in the encoded form.
+const BITS_BITMAP: u8 = 0;
+const BITS_GOLOMB_RICE: u8 = 1;
+/// rice parameter, number of set bits
+const GOLOMB_RICE_HEADER_LEN: usize = 1 + 8;
+/// Largest filter that can be Golomb-Rice coded. Decoding allocates the whole bitmap, and a
+/// small coded input must not be able to claim an arbitrarily large filter.
+const MAX_GOLOMB_RICE_BITS: usize = 1 << 32;
+/// Filter bits a Golomb-Rice coded filter may have per set bit, plus one. The coded body
+/// takes at least a bit per set bit, so this bounds the bitmap decoding allocates by the
+/// size of the input. Sparser filters are stored as a bitmap.
+const MAX_GOLOMB_RICE_BITS_PER_SET_BIT: usize = 1 << 12;
+/// Ids of the prefix extractors in the encoded form.
+const PREFIX_NONE: u8 = 0;
+const PREFIX_FIXED_LENGTH: u8 = 1;
+const PREFIX_DELIMITER: u8 = 2;
+/// Prefixes are hashed with a different seed than whole keys, so a key added only as a
+/// prefix of longer keys is not reported by `has_key`.
+const PREFIX_SEED_MASK: u64 = 0x9e37_79b9_7f4a_7c15;
+/// Keys hashed and prefetched together by `add_keys` and `has_keys`.
+const BATCH_SIZE: usize = 16;
 
 /// BloomFilter gives a FIRM no or a PROBABLY yes using probabilistic data structure.
-struct SlatedbBloomFilter {
-    bit_set: Vec<bool>,
+#[derive(Clone)]
+pub struct SlatedbBloomFilter {
+    /// Bits packed 8 per byte, addressed with `set_bit`/`check_bit`.
+    buffer: Vec<u8>,
+    /// Number of addressable bits, `buffer` is rounded up to a whole byte.
+    num_bits: usize,
     /// Number of hash functions used to determine the bit to check for key existence.
     /// https://en.wikipedia.org/wiki/Bloom_filter#Optimal_number_of_hash_functions.
     num_probes: u32,
+    /// Seed of the key hash, filters only agree on a key if they share the seed.
+    seed: u64,
+    /// Prefixes added next to every key, for `may_contain_prefix`.
+    prefix_extractor: Option<PrefixExtractor>,
 }
 
 impl SlatedbBloomFilter {
     pub fn new(size: usize, num_probes: u32) -> Self {
+        Self::new_with_seed(size, num_probes, DEFAULT_SEED)
+    }
+
+    /// Panics on a size of zero.
+    pub fn new_with_seed(size: usize, num_probes: u32, seed: u64) -> Self {
+        assert!(size > 0, "size must be positive");
+        Self::from_buffer(vec![0u8; size.div_ceil(8)], size, num_probes, seed)
+    }
+
+    /// Create a filter over an already populated bit buffer.
+    pub(crate) fn from_buffer(
+        buffer: Vec<u8>,
+        num_bits: usize,
+        num_probes: u32,
+        seed: u64,
+    ) -> Self {
         Self {
-            bit_set: vec![false; size],
+            buffer,
+            num_bits,
             num_probes,
+            seed,
+            prefix_extractor: None,
         }
     }
 
-    pub fn decode(buf: &[u8]) -> SlatedbBloomFilter {
-        todo!()
+    /// Create a filter with bit count and probe count derived from `size`.
+    pub fn with_size(size: BloomFilterSize) -> Self {
+        Self::new(size.num_bits, size.num_probes)
     }
 
+    /// Also add the prefixes `extractor` finds in every key, to answer `may_contain_prefix`.
+    ///
+    /// Every prefix takes up room like a key, so size the filter for the number of keys plus
+    /// the number of distinct prefixes. Set the extractor before adding keys, prefixes of keys
+    /// added earlier are not in the filter.
+    ///
+    /// Panics on a fixed length of zero.
+    pub fn with_prefix_extractor(mut self, extractor: PrefixExtractor) -> Self {
+        assert!(
+            extractor != PrefixExtractor::FixedLength(0),
+            "prefix length must be positive"
+        );
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    pub fn prefix_extractor(&self) -> Option<PrefixExtractor> {
+        self.prefix_extractor
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    pub fn num_probes(&self) -> u32 {
+        self.num_probes
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Theoretical false positive rate of the filter once it holds `num_keys` keys.
+    pub fn false_positive_rate(&self, num_keys: usize) -> f64 {
+        false_positive_rate(self.num_bits, num_keys, self.num_probes)
+    }
+
+    /// Number of bytes used by the filter, including the packed bit buffer.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.buffer.capacity()
+    }
+
+    /// Fraction of bits that are set, a filter with half its bits set is "full" at the
+    /// optimal number of probes.
+    pub fn fill_ratio(&self) -> f64 {
+        count_set_bits(&self.buffer) as f64 / self.num_bits as f64
+    }
+
+    /// Estimated number of distinct keys added, derived from the number of bits set.
+    ///
+    /// Returns `usize::MAX` once every bit is set and the estimate diverges.
+    pub fn estimated_num_keys(&self) -> usize {
+        estimate_num_keys(self.num_bits, count_set_bits(&self.buffer), self.num_probes)
+    }
+
+    /// Probability that a key never added is reported present, given the bits set now.
+    ///
+    /// A lookup is a false positive when all `num_probes` probed bits are set, which for
+    /// independent probes is `fill_ratio ^ num_probes`. Unlike `false_positive_rate` this does
+    /// not need to know how many keys were added, so it also works on decoded filters.
+    pub fn current_false_positive_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.num_probes as i32)
+    }
+
+    /// Decode a filter written by [`SlatedbBloomFilter::encode`].
+    ///
+    /// Copies the bit buffer, use [`BloomFilterView`] to query encoded bytes in place.
+    /// Never panics, corrupt or truncated input is reported as an error.
+    pub fn decode(buf: &[u8]) -> Result<SlatedbBloomFilter, FilterDecodeError> {
+        let header = decode_header(buf)?;
+        match header.bit_encoding {
+            BitEncoding::Bitmap => Ok(BloomFilterView::with_header(buf, header)?.to_filter()),
+            BitEncoding::GolombRice => decode_golomb_rice(buf, header),
+        }
+    }
+
+    /// Encode the filter so it can be stored next to a sorted run, with the bits as a bitmap.
     pub fn encode(&self) -> Bytes {
-        todo!()
+        self.encode_with(BitEncoding::Bitmap)
+    }
+
+    /// Encode the filter with the bits stored in `bit_encoding`.
+    ///
+    /// Layout (big-endian):
+    /// ```text
+    /// | version (u8) | hash algorithm (u8) | num_probes (u32) | seed (u64) | num_bits (u64) |
+    /// | prefix extractor (u8) | prefix length or delimiter (u64) | bit encoding (u8) |
+    /// | bits | crc32 of everything before it (u32) |
+    /// ```
+    /// The number of bits is stored because `hash_key` maps probes modulo the filter
+    /// size, so it must survive the round trip exactly. A filter with zero bits can not
+    /// answer queries and is rejected by `decode`. The prefix extractor is 0 for none, 1 for
+    /// a fixed length and 2 for a delimiter, a reader must extract prefixes the same way the
+    /// writer did.
+    ///
+    /// The bit encoding is 0 for a bitmap of `ceil(num_bits / 8)` bytes and 1 for Golomb-Rice
+    /// coding, see [`BitEncoding::GolombRice`]:
+    /// ```text
+    /// | rice parameter k (u8) | number of set bits (u64) | coded gaps, zero padded to a byte |
+    /// ```
+    ///
+    /// Panics if Golomb-Rice coding a filter of more than 2^32 bits.
+    pub fn encode_with(&self, bit_encoding: BitEncoding) -> Bytes {
+        encode_bits(
+            &self.buffer,
+            self.num_bits,
+            self.num_probes,
+            self.seed,
+            self.prefix_extractor,
+            bit_encoding,
+        )
+    }
+
+    /// Filter holding the keys of both filters, as if every key had been added to one filter.
+    pub fn union(&self, other: &SlatedbBloomFilter) -> Result<Self, IncompatibleFilterError> {
+        let mut result = self.clone();
+        result.union_with(other)?;
+        Ok(result)
+    }
+
+    /// Filter holding the keys present in both filters.
+    ///
+    /// A key added to only one filter can still be reported present when the other filter
+    /// has its bits set by other keys, so the false positive rate is at least as high as
+    /// either input, and higher than a filter built from the intersection of the key sets.
+    pub fn intersect(&self, other: &SlatedbBloomFilter) -> Result<Self, IncompatibleFilterError> {
+        let mut result = self.clone();
+        result.intersect_with(other)?;
+        Ok(result)
+    }
+
+    /// In-place [`SlatedbBloomFilter::union`].
+    pub fn union_with(
+        &mut self,
+        other: &SlatedbBloomFilter,
+    ) -> Result<(), IncompatibleFilterError> {
+        self.check_compatible(other)?;
+        for (byte, other) in self.buffer.iter_mut().zip(&other.buffer) {
+            *byte |= other;
+        }
+        Ok(())
+    }
+
+    /// In-place [`SlatedbBloomFilter::intersect`].
+    pub fn intersect_with(
+        &mut self,
+        other: &SlatedbBloomFilter,
+    ) -> Result<(), IncompatibleFilterError> {
+        self.check_compatible(other)?;
+        for (byte, other) in self.buffer.iter_mut().zip(&other.buffer) {
+            *byte &= other;
+        }
+        Ok(())
     }
 
+    /// Filters can only be combined if every key maps to the same bits in both.
+    fn check_compatible(&self, other: &SlatedbBloomFilter) -> Result<(), IncompatibleFilterError> {
+        if self.num_bits != other.num_bits {
+            return Err(IncompatibleFilterError::NumBits {
+                left: self.num_bits,
+                right: other.num_bits,
+            });
+        }
+        if self.num_probes != other.num_probes {
+            return Err(IncompatibleFilterError::NumProbes {
+                left: self.num_probes,
+                right: other.num_probes,
+            });
+        }
+        if self.seed != other.seed {
+            return Err(IncompatibleFilterError::Seed {
+                left: self.seed,
+                right: other.seed,
+            });
+        }
+        if self.prefix_extractor != other.prefix_extractor {
+            return Err(IncompatibleFilterError::PrefixExtractor {
+                left: self.prefix_extractor,
+                right: other.prefix_extractor,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add the key, and its prefixes if the filter has a prefix extractor.
     pub fn add_key(&mut self, key: &[u8]) {
         let indices = self.hash_key(key);
         for index in indices {
-            self.bit_set[index] = true;
+            set_bit(index, &mut self.buffer);
+        }
+        self.add_prefixes(key);
+    }
+
+    /// [`SlatedbBloomFilter::add_key`] for many keys, see [`SlatedbBloomFilter::has_keys`].
+    pub fn add_keys<K: AsRef<[u8]>>(&mut self, keys: &[K]) {
+        let mut indices = Vec::with_capacity(BATCH_SIZE * self.num_probes as usize);
+        for batch in keys.chunks(BATCH_SIZE) {
+            self.probe_batch(batch, &mut indices);
+            for &index in &indices {
+                set_bit(index, &mut self.buffer);
+            }
+            for key in batch {
+                self.add_prefixes(key.as_ref());
+            }
+        }
+    }
+
+    fn add_prefixes(&mut self, key: &[u8]) {
+        if let Some(extractor) = self.prefix_extractor {
+            let seed = self.seed ^ PREFIX_SEED_MASK;
+            for prefix in extractor.prefixes(key) {
+                for index in probes(prefix, seed, self.num_probes, self.num_bits) {
+                    set_bit(index, &mut self.buffer);
+                }
+            }
         }
     }
 
     pub fn has_key(&self, key: &[u8]) -> bool {
         let indices = self.hash_key(key);
         for index in indices {
-            if !self.bit_set[index] {
+            if !check_bit(index, &self.buffer) {
                 return false;
             }
         }
         true // if all bits are 1, key may be in set (false positive possible)
     }
 
-    pub fn hash_key(&self, key: &[u8]) -> Vec<usize> {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let hash = hasher.finish();
-        let size = self.bit_set.len() as u64;
-        let mut result = Vec::with_capacity(self.num_probes as usize);
-        // double hashing scheme to generate multiple unique indices from a single hash value.
-        let h1 = (hash << 32) >> 32;
-        let h2 = hash >> 32;
-        for i in 0..self.num_probes {
-            let index = ((h1.wrapping_add(i as u64 * h2)) % size) as usize;
-            result.push(index);
+    /// [`SlatedbBloomFilter::has_key`] for many keys, bit `i % 64` of word `i / 64` is set
+    /// when `keys[i]` may be in the filter.
+    ///
+    /// Keys are hashed in batches and every probed byte of a batch is prefetched before any
+    /// is read, so the cache misses of a batch overlap instead of being paid one at a time.
+    /// Faster than calling `has_key` in a loop once the filter is larger than the CPU cache.
+    pub fn has_keys<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<u64> {
+        let mut bitmap = vec![0u64; keys.len().div_ceil(64)];
+        if self.num_probes == 0 {
+            // no probes, every key may be present.
+            for position in 0..keys.len() {
+                bitmap[position / 64] |= 1 << (position % 64);
+            }
+            return bitmap;
+        }
+        let mut indices = Vec::with_capacity(BATCH_SIZE * self.num_probes as usize);
+        for (batch_index, batch) in keys.chunks(BATCH_SIZE).enumerate() {
+            self.probe_batch(batch, &mut indices);
+            for (i, probes) in indices.chunks(self.num_probes as usize).enumerate() {
+                if probes.iter().all(|&index| check_bit(index, &self.buffer)) {
+                    let position = batch_index * BATCH_SIZE + i;
+                    bitmap[position / 64] |= 1 << (position % 64);
+                }
+            }
+        }
+        bitmap
+    }
+
+    /// Replace `indices` with the probes of up to `BATCH_SIZE` keys, `num_probes` per key,
+    /// and prefetch every byte they touch.
+    fn probe_batch<K: AsRef<[u8]>>(&self, batch: &[K], indices: &mut Vec<usize>) {
+        indices.clear();
+        for key in batch {
+            let hash = hash64(key.as_ref(), self.seed);
+            for index in probes_from_hash(hash, self.num_probes, self.num_bits) {
+                prefetch_bit(index, &self.buffer);
+                indices.push(index);
+            }
+        }
+    }
+
+    /// Whether a key starting with `prefix` may have been added, false is a FIRM no.
+    ///
+    /// Every key starting with `prefix` shares the prefixes the extractor finds in `prefix`
+    /// itself, so those are checked: `user:42:` with a `:` delimiter checks `user:` and
+    /// `user:42:`. Always true without a prefix extractor, or when `prefix` is too short to
+    /// hold an extracted prefix, as the filter can not rule anything out then.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        check_prefix(
+            &self.buffer,
+            self.num_bits,
+            self.num_probes,
+            self.seed,
+            self.prefix_extractor,
+            prefix,
+        )
+    }
+
+    /// Positions probed for `key`, computed lazily so a lookup can stop at the first unset one.
+    pub fn hash_key(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        probes(key, self.seed, self.num_probes, self.num_bits)
+    }
+}
+
+/// Shows the parameters only, the bits would drown them out.
+impl fmt::Debug for SlatedbBloomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlatedbBloomFilter")
+            .field("num_bits", &self.num_bits)
+            .field("num_probes", &self.num_probes)
+            .field("seed", &self.seed)
+            .field("prefix_extractor", &self.prefix_extractor)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How [`SlatedbBloomFilter::encode_with`] stores the bits of a filter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitEncoding {
+    /// One bit per filter bit, viewable in place by [`BloomFilterView`].
+    #[default]
+    Bitmap,
+    /// The sorted positions of the set bits, each gap Golomb-Rice coded.
+    ///
+    /// Smaller than the bitmap while under roughly a third of the bits are set, so it suits
+    /// filters sized for many more keys than they hold. Decoding costs a pass over the
+    /// coded bits and the filter can not be viewed in place. A filter with fewer than one
+    /// set bit per 4096 bits, e.g. a large empty one, is stored as a bitmap instead.
+    GolombRice,
+}
+
+/// Read-only view of an encoded [`SlatedbBloomFilter`] that answers `has_key` directly
+/// against the encoded bytes.
+///
+/// Creating a view parses the fixed-size header and borrows the bit buffer, and `has_key`
+/// does not allocate, so thousands of filters can be queried straight out of a memory mapped
+/// file or a shared `Bytes` without copying them.
+#[derive(Clone, Copy)]
+pub struct BloomFilterView<'a> {
+    buffer: &'a [u8],
+    num_bits: usize,
+    num_probes: u32,
+    seed: u64,
+    prefix_extractor: Option<PrefixExtractor>,
+}
+
+impl<'a> BloomFilterView<'a> {
+    /// View a filter written by [`SlatedbBloomFilter::encode`].
+    ///
+    /// Validates the header and the checksum, which reads the whole buffer once.
+    /// Golomb-Rice coded filters are rejected with `CompressedBits`, they have no bitmap to
+    /// borrow.
+    pub fn new(encoded: &'a [u8]) -> Result<Self, FilterDecodeError> {
+        Self::with_header(encoded, decode_header(encoded)?)
+    }
+
+    fn with_header(encoded: &'a [u8], header: Header) -> Result<Self, FilterDecodeError> {
+        let Header {
+            len: header_len,
+            num_bits,
+            num_probes,
+            seed,
+            prefix_extractor,
+            bit_encoding,
+        } = header;
+        if bit_encoding != BitEncoding::Bitmap {
+            return Err(FilterDecodeError::CompressedBits);
+        }
+        let mut buf = &encoded[header_len..];
+        let num_bytes = num_bits.div_ceil(8);
+        ensure_remaining(buf, num_bytes.saturating_add(CHECKSUM_LEN), header_len)?;
+        let expected_len = header_len + num_bytes + CHECKSUM_LEN;
+        if encoded.len() > expected_len {
+            return Err(FilterDecodeError::TrailingBytes {
+                expected: expected_len,
+                actual: encoded.len(),
+            });
+        }
+        let buffer = &buf[..num_bytes];
+        buf.advance(num_bytes);
+        let stored = buf.get_u32();
+        let computed = crc32fast::hash(&encoded[..header_len + num_bytes]);
+        if stored != computed {
+            return Err(FilterDecodeError::ChecksumMismatch {
+                expected: stored,
+                actual: computed,
+            });
+        }
+        Ok(Self {
+            buffer,
+            num_bits,
+            num_probes,
+            seed,
+            prefix_extractor,
+        })
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        probes(key, self.seed, self.num_probes, self.num_bits)
+            .all(|index| check_bit(index, self.buffer))
+    }
+
+    /// See [`SlatedbBloomFilter::may_contain_prefix`].
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        check_prefix(
+            self.buffer,
+            self.num_bits,
+            self.num_probes,
+            self.seed,
+            self.prefix_extractor,
+            prefix,
+        )
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    pub fn num_probes(&self) -> u32 {
+        self.num_probes
+    }
+
+    pub fn prefix_extractor(&self) -> Option<PrefixExtractor> {
+        self.prefix_extractor
+    }
+
+    /// Copy the viewed bits into an owned filter that keys can be added to.
+    pub fn to_filter(&self) -> SlatedbBloomFilter {
+        let mut filter = SlatedbBloomFilter::from_buffer(
+            self.buffer.to_vec(),
+            self.num_bits,
+            self.num_probes,
+            self.seed,
+        );
+        filter.prefix_extractor = self.prefix_extractor;
+        filter
+    }
+}
+
+/// `filter |= &other` for [`SlatedbBloomFilter::union_with`].
+///
+/// Panics if the filters are incompatible, use `union_with` to handle the error.
+impl BitOrAssign<&SlatedbBloomFilter> for SlatedbBloomFilter {
+    fn bitor_assign(&mut self, other: &SlatedbBloomFilter) {
+        if let Err(err) = self.union_with(other) {
+            panic!("can not union bloom filters: {}", err);
+        }
+    }
+}
+
+/// `filter &= &other` for [`SlatedbBloomFilter::intersect_with`].
+///
+/// Panics if the filters are incompatible, use `intersect_with` to handle the error.
+impl BitAndAssign<&SlatedbBloomFilter> for SlatedbBloomFilter {
+    fn bitand_assign(&mut self, other: &SlatedbBloomFilter) {
+        if let Err(err) = self.intersect_with(other) {
+            panic!("can not intersect bloom filters: {}", err);
+        }
+    }
+}
+
+impl Filter for SlatedbBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    fn estimated_len(&self) -> usize {
+        self.estimated_num_keys()
+    }
+
+    fn encode(&self) -> Bytes {
+        SlatedbBloomFilter::encode(self)
+    }
+}
+
+/// Encode a packed bit buffer in the layout documented on [`SlatedbBloomFilter::encode`].
+pub(crate) fn encode_bits(
+    buffer: &[u8],
+    num_bits: usize,
+    num_probes: u32,
+    seed: u64,
+    prefix_extractor: Option<PrefixExtractor>,
+    bit_encoding: BitEncoding,
+) -> Bytes {
+    let mut encoded = BytesMut::with_capacity(HEADER_LEN + buffer.len() + CHECKSUM_LEN);
+    encoded.put_u8(FORMAT_VERSION);
+    encoded.put_u8(HASH_XXH64);
+    encoded.put_u32(num_probes);
+    encoded.put_u64(seed);
+    encoded.put_u64(num_bits as u64);
+    let (kind, parameter) = match prefix_extractor {
+        None => (PREFIX_NONE, 0),
+        Some(PrefixExtractor::FixedLength(n)) => (PREFIX_FIXED_LENGTH, n as u64),
+        Some(PrefixExtractor::Delimiter(delimiter)) => (PREFIX_DELIMITER, delimiter as u64),
+    };
+    encoded.put_u8(kind);
+    encoded.put_u64(parameter);
+    match bit_encoding {
+        BitEncoding::Bitmap => {
+            encoded.put_u8(BITS_BITMAP);
+            encoded.put_slice(buffer);
+        }
+        BitEncoding::GolombRice => {
+            assert!(
+                num_bits <= MAX_GOLOMB_RICE_BITS,
+                "filters of more than 2^32 bits can not be golomb-rice coded, got {}",
+                num_bits
+            );
+            let set_bits = || (0..num_bits).filter(|&bit| check_bit(bit, buffer));
+            let num_set_bits = set_bits().count();
+            if num_bits > (num_set_bits + 1) * MAX_GOLOMB_RICE_BITS_PER_SET_BIT {
+                // too sparse for decode to accept.
+                encoded.put_u8(BITS_BITMAP);
+                encoded.put_slice(buffer);
+            } else {
+                let k = golomb::rice_parameter(num_set_bits, num_bits);
+                encoded.put_u8(BITS_GOLOMB_RICE);
+                encoded.put_u8(k);
+                encoded.put_u64(num_set_bits as u64);
+                encoded.put_slice(&golomb::encode(set_bits(), k));
+            }
+        }
+    }
+    encoded.put_u32(crc32fast::hash(&encoded));
+    encoded.freeze()
+}
+
+/// Fields of an encoded filter header.
+struct Header {
+    /// Length of the header, where the bits start.
+    len: usize,
+    num_bits: usize,
+    num_probes: u32,
+    seed: u64,
+    prefix_extractor: Option<PrefixExtractor>,
+    bit_encoding: BitEncoding,
+}
+
+fn decode_header(encoded: &[u8]) -> Result<Header, FilterDecodeError> {
+    let mut buf = encoded;
+    ensure_remaining(buf, 1, 0)?;
+    let version = buf.get_u8();
+    let len = match version {
+        1 => HEADER_LEN_V1,
+        2 => HEADER_LEN_V2,
+        FORMAT_VERSION => HEADER_LEN,
+        _ => return Err(FilterDecodeError::UnknownVersion(version)),
+    };
+    ensure_remaining(buf, len - 1, 1)?;
+    let hash_algorithm = buf.get_u8();
+    if hash_algorithm != HASH_XXH64 {
+        return Err(FilterDecodeError::UnknownHashAlgorithm(hash_algorithm));
+    }
+    let num_probes = buf.get_u32();
+    let seed = buf.get_u64();
+    let num_bits = usize::try_from(buf.get_u64())
+        .map_err(|_| FilterDecodeError::InvalidHeader("num_bits does not fit in memory"))?;
+    if num_bits == 0 {
+        return Err(FilterDecodeError::InvalidHeader("num_bits is zero"));
+    }
+    let prefix_extractor = if version == 1 {
+        None
+    } else {
+        decode_prefix_extractor(buf.get_u8(), buf.get_u64())?
+    };
+    let bit_encoding = if version < 3 {
+        BitEncoding::Bitmap
+    } else {
+        match buf.get_u8() {
+            BITS_BITMAP => BitEncoding::Bitmap,
+            BITS_GOLOMB_RICE => BitEncoding::GolombRice,
+            id => return Err(FilterDecodeError::UnknownBitEncoding(id)),
         }
-        result
+    };
+    Ok(Header {
+        len,
+        num_bits,
+        num_probes,
+        seed,
+        prefix_extractor,
+        bit_encoding,
+    })
+}
+
+/// Rebuild the bitmap of a Golomb-Rice coded filter from its set bit positions.
+fn decode_golomb_rice(
+    encoded: &[u8],
+    header: Header,
+) -> Result<SlatedbBloomFilter, FilterDecodeError> {
+    ensure_remaining(
+        &encoded[header.len..],
+        GOLOMB_RICE_HEADER_LEN + CHECKSUM_LEN,
+        header.len,
+    )?;
+    let body = verify_checksum(encoded)?;
+    let mut buf = &body[header.len..];
+    let k = buf.get_u8();
+    let num_set_bits = buf.get_u64();
+    if header.num_bits > MAX_GOLOMB_RICE_BITS {
+        return Err(FilterDecodeError::InvalidHeader(
+            "num_bits is too large for golomb-rice coding",
+        ));
+    }
+    if k >= 64 {
+        return Err(FilterDecodeError::InvalidHeader(
+            "rice parameter is too large",
+        ));
+    }
+    let num_set_bits = usize::try_from(num_set_bits)
+        .map_err(|_| FilterDecodeError::InvalidHeader("number of set bits does not fit"))?;
+    // every set bit takes at least k + 1 coded bits, check that before trusting the count
+    // to bound num_bits, and so the allocation below.
+    if num_set_bits.saturating_mul(k as usize + 1) > buf.len().saturating_mul(8) {
+        return Err(FilterDecodeError::InvalidHeader(
+            "golomb-rice coded bits do not match the header",
+        ));
+    }
+    if header.num_bits > (num_set_bits + 1).saturating_mul(MAX_GOLOMB_RICE_BITS_PER_SET_BIT) {
+        return Err(FilterDecodeError::InvalidHeader(
+            "num_bits is too large for golomb-rice coding",
+        ));
+    }
+    let positions = golomb::decode(buf, num_set_bits, k, header.num_bits).ok_or(
+        FilterDecodeError::InvalidHeader("golomb-rice coded bits do not match the header"),
+    )?;
+    let mut buffer = vec![0; header.num_bits.div_ceil(8)];
+    for bit in positions {
+        set_bit(bit, &mut buffer);
     }
+    let mut filter =
+        SlatedbBloomFilter::from_buffer(buffer, header.num_bits, header.num_probes, header.seed);
+    filter.prefix_extractor = header.prefix_extractor;
+    Ok(filter)
+}
+
+fn decode_prefix_extractor(
+    kind: u8,
+    parameter: u64,
+) -> Result<Option<PrefixExtractor>, FilterDecodeError> {
+    match kind {
+        PREFIX_NONE if parameter == 0 => Ok(None),
+        PREFIX_FIXED_LENGTH if parameter > 0 => usize::try_from(parameter)
+            .map(|n| Some(PrefixExtractor::FixedLength(n)))
+            .map_err(|_| FilterDecodeError::InvalidHeader("prefix length does not fit in memory")),
+        PREFIX_DELIMITER => u8::try_from(parameter)
+            .map(|delimiter| Some(PrefixExtractor::Delimiter(delimiter)))
+            .map_err(|_| FilterDecodeError::InvalidHeader("prefix delimiter is not a byte")),
+        PREFIX_NONE | PREFIX_FIXED_LENGTH => Err(FilterDecodeError::InvalidHeader(
+            "invalid prefix extractor parameter",
+        )),
+        _ => Err(FilterDecodeError::UnknownPrefixExtractor(kind)),
+    }
+}
+
+/// Whether every prefix `extractor` finds in `prefix` is set in `buffer`, the prefix query of
+/// both the owned filter and the view.
+fn check_prefix(
+    buffer: &[u8],
+    num_bits: usize,
+    num_probes: u32,
+    seed: u64,
+    extractor: Option<PrefixExtractor>,
+    prefix: &[u8],
+) -> bool {
+    match extractor {
+        None => true,
+        Some(extractor) => extractor.prefixes(prefix).all(|prefix| {
+            probes(prefix, seed ^ PREFIX_SEED_MASK, num_probes, num_bits)
+                .all(|index| check_bit(index, buffer))
+        }),
+    }
+}
+
+/// Number of bits set in buf
+pub(crate) fn count_set_bits(buf: &[u8]) -> usize {
+    buf.iter().map(|byte| byte.count_ones() as usize).sum()
+}
+
+/// Estimate the number of distinct keys in a filter from the number of bits set.
+///
+/// Swamidass & Baldi: `n = -(m / k) * ln(1 - X / m)` for `X` set bits out of `m`.
+/// https://en.wikipedia.org/wiki/Bloom_filter#Approximating_the_number_of_items_in_a_Bloom_filter
+pub(crate) fn estimate_num_keys(num_bits: usize, set_bits: usize, num_probes: u32) -> usize {
+    if set_bits >= num_bits {
+        // every bit is set, the filter can no longer tell how many keys it holds.
+        return usize::MAX;
+    }
+    let m = num_bits as f64;
+    let k = num_probes as f64;
+    (-(m / k) * (1.0 - set_bits as f64 / m).ln()).round() as usize
 }
 
 /// Set the bit and index bit in buf
-fn set_bit(bit: usize, buf: &mut [u8]) {
+pub(crate) fn set_bit(bit: usize, buf: &mut [u8]) {
     let byte = bit / 8;
     let bit_in_byte = bit % 8;
     buf[byte] |= 1 << bit_in_byte;
 }
 
+/// Hint the CPU to start loading the byte holding `bit`, a no-op off x86_64.
+#[inline]
+fn prefetch_bit(bit: usize, buf: &[u8]) {
+    debug_assert!(bit / 8 < buf.len());
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: the pointer stays inside `buf`, and a prefetch never faults or writes anyway.
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(buf.as_ptr().add(bit / 8) as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = (bit, buf);
+}
+
 /// Check if bit at index bit is one
-fn check_bit(bit: usize, buf: &[u8]) -> bool {
+pub(crate) fn check_bit(bit: usize, buf: &[u8]) -> bool {
     let byte = bit / 8;
     let bit_in_byte = bit % 8;
     (buf[byte] & (1 << bit_in_byte)) != 0
@@ -81,8 +809,61 @@ fn optimal_num_probes(bits_per_key: u32) -> u16 {
     (bits_per_key as f32 * 0.69) as u16
 }
 
+/// Calculate the bits per key needed to stay under a false positive rate.
+fn bits_per_key_for_fpr(fpr: f64) -> u32 {
+    // -ln(p) / ln(2)^2
+    // https://en.wikipedia.org/wiki/Bloom_filter#Optimal_number_of_hash_functions
+    (-fpr.ln() / (std::f64::consts::LN_2 * std::f64::consts::LN_2)).ceil() as u32
+}
+
+/// Theoretical false positive rate `(1 - e^(-k * n / m))^k` of a filter with `num_bits` bits
+/// and `num_probes` probes holding `num_keys` keys.
+pub(crate) fn false_positive_rate(num_bits: usize, num_keys: usize, num_probes: u32) -> f64 {
+    let k = num_probes as f64;
+    let exponent = -k * num_keys as f64 / num_bits as f64;
+    (1.0 - exponent.exp()).powf(k)
+}
+
+/// Bit count and probe count of a filter sized for an expected number of keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomFilterSize {
+    pub num_bits: usize,
+    pub num_probes: u32,
+    pub expected_keys: usize,
+}
+
+impl BloomFilterSize {
+    /// Size a filter that spends `bits_per_key` bits on each of `expected_keys` keys.
+    pub fn with_bits_per_key(expected_keys: usize, bits_per_key: u32) -> Self {
+        assert!(bits_per_key > 0, "bits_per_key must be positive");
+        Self {
+            num_bits: expected_keys.max(1) * bits_per_key as usize,
+            num_probes: (optimal_num_probes(bits_per_key) as u32).max(1),
+            expected_keys,
+        }
+    }
+
+    /// Size a filter whose false positive rate stays at or below `fpr` once it holds
+    /// `expected_keys` keys.
+    pub fn with_false_positive_rate(expected_keys: usize, fpr: f64) -> Self {
+        assert!(fpr > 0.0 && fpr < 1.0, "fpr must be in (0, 1), got {}", fpr);
+        Self::with_bits_per_key(expected_keys, bits_per_key_for_fpr(fpr))
+    }
+
+    pub fn bits_per_key(&self) -> f64 {
+        self.num_bits as f64 / self.expected_keys.max(1) as f64
+    }
+
+    /// Theoretical false positive rate once the filter holds `expected_keys` keys.
+    pub fn false_positive_rate(&self) -> f64 {
+        false_positive_rate(self.num_bits, self.expected_keys, self.num_probes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
 
     use super::*;
 
@@ -100,6 +881,647 @@ mod tests {
         assert!(!filter.has_key("kiwi".as_bytes()));
     }
 
+    #[test]
+    fn test_encode_decode_round_trip() {
+        // 1003 bits is not a multiple of 8, so the last byte is only partially used.
+        let mut filter = SlatedbBloomFilter::new_with_seed(1003, 4, 7);
+        let keys: Vec<String> = (0..100).map(|i| format!("key-{}", i)).collect();
+        for key in &keys {
+            filter.add_key(key.as_bytes());
+        }
+
+        let encoded = filter.encode();
+        assert_eq!(
+            encoded.len(),
+            HEADER_LEN + 1003usize.div_ceil(8) + CHECKSUM_LEN
+        );
+
+        let decoded = SlatedbBloomFilter::decode(&encoded).unwrap();
+        assert_eq!(decoded.num_probes, filter.num_probes);
+        assert_eq!(decoded.seed, 7);
+        assert_eq!(decoded.num_bits, filter.num_bits);
+        assert_eq!(decoded.buffer, filter.buffer);
+        for key in &keys {
+            assert!(decoded.has_key(key.as_bytes()));
+        }
+        for i in 0..1000 {
+            let absent = format!("absent-{}", i);
+            assert_eq!(
+                decoded.has_key(absent.as_bytes()),
+                filter.has_key(absent.as_bytes())
+            );
+        }
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn test_bits_are_packed() {
+        let filter = SlatedbBloomFilter::new(8_000_000, 6);
+        assert_eq!(filter.buffer.len(), 1_000_000);
+        assert!(filter.memory_size() < 8_000_000 / 8 + 128);
+    }
+
+    fn encoded_filter() -> Bytes {
+        let mut filter = SlatedbBloomFilter::new_with_seed(1003, 4, 7);
+        for i in 0..100 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        filter.encode()
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let encoded = encoded_filter();
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded[..10]).err(),
+            Some(FilterDecodeError::Truncated {
+                expected: HEADER_LEN,
+                actual: 10
+            })
+        );
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded[..encoded.len() - 1]).err(),
+            Some(FilterDecodeError::Truncated {
+                expected: encoded.len(),
+                actual: encoded.len() - 1
+            })
+        );
+        let mut longer = encoded.to_vec();
+        longer.push(0);
+        assert_eq!(
+            SlatedbBloomFilter::decode(&longer).err(),
+            Some(FilterDecodeError::TrailingBytes {
+                expected: encoded.len(),
+                actual: encoded.len() + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_corrupted() {
+        let mut encoded = encoded_filter().to_vec();
+        encoded[HEADER_LEN + 3] ^= 0x10;
+        assert!(matches!(
+            SlatedbBloomFilter::decode(&encoded),
+            Err(FilterDecodeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_unknown_version_and_hash() {
+        let mut encoded = encoded_filter().to_vec();
+        encoded[HEADER_LEN_V2] = 5;
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded).err(),
+            Some(FilterDecodeError::UnknownBitEncoding(5))
+        );
+        encoded[HEADER_LEN_V1] = 7;
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded).err(),
+            Some(FilterDecodeError::UnknownPrefixExtractor(7))
+        );
+        encoded[1] = 9;
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded).err(),
+            Some(FilterDecodeError::UnknownHashAlgorithm(9))
+        );
+        encoded[0] = 4;
+        assert_eq!(
+            SlatedbBloomFilter::decode(&encoded).err(),
+            Some(FilterDecodeError::UnknownVersion(4))
+        );
+    }
+
+    #[test]
+    fn test_decode_version_1() {
+        let mut filter = SlatedbBloomFilter::new_with_seed(1003, 4, 7);
+        for i in 0..100 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        // version 1 has no prefix extractor between the header and the bits.
+        let mut encoded = BytesMut::new();
+        encoded.put_u8(1);
+        encoded.put_u8(HASH_XXH64);
+        encoded.put_u32(4);
+        encoded.put_u64(7);
+        encoded.put_u64(1003);
+        encoded.put_slice(&filter.buffer);
+        encoded.put_u32(crc32fast::hash(&encoded));
+
+        let decoded = SlatedbBloomFilter::decode(&encoded).unwrap();
+        assert_eq!(decoded.prefix_extractor(), None);
+        assert_eq!(decoded.encode(), filter.encode());
+        let view = BloomFilterView::new(&encoded).unwrap();
+        assert_eq!(view.buffer.as_ptr(), encoded[HEADER_LEN_V1..].as_ptr());
+        assert!(view.has_key(b"key-42"));
+    }
+
+    #[test]
+    fn test_decode_version_2() {
+        let filter = encoded_filter();
+        // version 2 has no bit encoding, the bits are always a bitmap.
+        let mut encoded = BytesMut::new();
+        encoded.put_u8(2);
+        encoded.put_slice(&filter[1..HEADER_LEN_V2]);
+        encoded.put_slice(&filter[HEADER_LEN..filter.len() - CHECKSUM_LEN]);
+        encoded.put_u32(crc32fast::hash(&encoded));
+
+        let decoded = SlatedbBloomFilter::decode(&encoded).unwrap();
+        assert_eq!(decoded.encode(), filter);
+        let view = BloomFilterView::new(&encoded).unwrap();
+        assert_eq!(view.buffer.as_ptr(), encoded[HEADER_LEN_V2..].as_ptr());
+        assert!(view.has_key(b"key-42"));
+    }
+
+    #[test]
+    fn test_golomb_rice_round_trip() {
+        // sized for 10_000 keys but holding 500, so about 2.5% of the bits are set.
+        let size = BloomFilterSize::with_false_positive_rate(10_000, 0.01);
+        let mut filter = SlatedbBloomFilter::with_size(size)
+            .with_prefix_extractor(PrefixExtractor::Delimiter(b':'));
+        for i in 0..500 {
+            filter.add_key(format!("user:{}", i).as_bytes());
+        }
+        let bitmap = filter.encode();
+        let coded = filter.encode_with(BitEncoding::GolombRice);
+        // ~7.5 bits per set bit against ~40 bits of bitmap per set bit.
+        assert!(
+            coded.len() * 4 < bitmap.len(),
+            "{} vs {}",
+            coded.len(),
+            bitmap.len()
+        );
+
+        let decoded = SlatedbBloomFilter::decode(&coded).unwrap();
+        assert_eq!(decoded.encode(), bitmap);
+        assert_eq!(decoded.encode_with(BitEncoding::GolombRice), coded);
+        assert!((0..500).all(|i| decoded.has_key(format!("user:{}", i).as_bytes())));
+        assert!(decoded.may_contain_prefix(b"user:"));
+        assert_eq!(
+            BloomFilterView::new(&coded).err(),
+            Some(FilterDecodeError::CompressedBits)
+        );
+
+        // an empty filter codes no positions at all.
+        let empty = SlatedbBloomFilter::new(1000, 4).encode_with(BitEncoding::GolombRice);
+        assert_eq!(empty.len(), HEADER_LEN + 9 + CHECKSUM_LEN);
+        assert_eq!(
+            SlatedbBloomFilter::decode(&empty).unwrap().estimated_len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_golomb_rice_decode_errors() {
+        let mut filter = SlatedbBloomFilter::new_with_seed(1003, 4, 7);
+        for i in 0..20 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let encoded = filter.encode_with(BitEncoding::GolombRice);
+        for len in 0..encoded.len() {
+            assert!(SlatedbBloomFilter::decode(&encoded[..len]).is_err());
+        }
+        for byte in 0..encoded.len() {
+            for bit in 0..8 {
+                let mut damaged = encoded.to_vec();
+                damaged[byte] ^= 1 << bit;
+                assert!(SlatedbBloomFilter::decode(&damaged).is_err());
+            }
+        }
+
+        // well formed checksums around bad coded bits.
+        let with_checksum = |body: &[u8]| {
+            let mut encoded = body.to_vec();
+            encoded.extend_from_slice(&crc32fast::hash(body).to_be_bytes());
+            encoded
+        };
+        let body = &encoded[..encoded.len() - CHECKSUM_LEN];
+        let mut more_set_bits = body.to_vec();
+        more_set_bits[HEADER_LEN + 1..HEADER_LEN + 9].copy_from_slice(&1000u64.to_be_bytes());
+        assert_eq!(
+            SlatedbBloomFilter::decode(&with_checksum(&more_set_bits)).err(),
+            Some(FilterDecodeError::InvalidHeader(
+                "golomb-rice coded bits do not match the header"
+            ))
+        );
+        let mut large_k = body.to_vec();
+        large_k[HEADER_LEN] = 64;
+        assert_eq!(
+            SlatedbBloomFilter::decode(&with_checksum(&large_k)).err(),
+            Some(FilterDecodeError::InvalidHeader(
+                "rice parameter is too large"
+            ))
+        );
+        // a few bytes must not be able to claim a huge bitmap.
+        for num_bits in [1u64 << 40, 1 << 31] {
+            let mut huge = body.to_vec();
+            huge[14..22].copy_from_slice(&num_bits.to_be_bytes());
+            assert_eq!(
+                SlatedbBloomFilter::decode(&with_checksum(&huge)).err(),
+                Some(FilterDecodeError::InvalidHeader(
+                    "num_bits is too large for golomb-rice coding"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn test_golomb_rice_falls_back_to_bitmap_when_too_sparse() {
+        let mut filter = SlatedbBloomFilter::new(1 << 20, 4);
+        let empty = filter.encode_with(BitEncoding::GolombRice);
+        assert_eq!(empty, filter.encode());
+        filter.add_key(b"apple");
+        assert_eq!(filter.encode_with(BitEncoding::GolombRice), filter.encode());
+        for i in 0..300 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let coded = filter.encode_with(BitEncoding::GolombRice);
+        assert!(coded.len() < filter.encode().len());
+        assert_eq!(
+            SlatedbBloomFilter::decode(&coded).unwrap().encode(),
+            filter.encode()
+        );
+    }
+
+    fn prefix_filter(extractor: PrefixExtractor) -> SlatedbBloomFilter {
+        let mut filter = SlatedbBloomFilter::with_size(BloomFilterSize::with_bits_per_key(300, 10))
+            .with_prefix_extractor(extractor);
+        for user in 0..50 {
+            for item in 0..2 {
+                filter.add_key(format!("user:{:03}:{}", user, item).as_bytes());
+            }
+        }
+        filter
+    }
+
+    #[test]
+    fn test_may_contain_prefix_with_delimiter() {
+        let filter = prefix_filter(PrefixExtractor::Delimiter(b':'));
+        for user in 0..50 {
+            assert!(filter.may_contain_prefix(format!("user:{:03}:", user).as_bytes()));
+        }
+        let false_positives = (50..1050)
+            .filter(|user| filter.may_contain_prefix(format!("user:{:03}:", user).as_bytes()))
+            .count();
+        assert!(false_positives < 30, "{}", false_positives);
+        assert!(!filter.may_contain_prefix(b"order:"));
+        assert!(!filter.may_contain_prefix(b"order:001:"));
+        // "user:00" only holds the prefix "user:", which was added.
+        assert!(filter.may_contain_prefix(b"user:00"));
+        // no delimiter, nothing to check.
+        assert!(filter.may_contain_prefix(b"order"));
+        // prefixes are hashed apart from whole keys.
+        assert!(filter.has_key(b"user:001:0"));
+        assert!(!filter.has_key(b"user:001:"));
+        assert!(!filter.has_key(b"user:"));
+    }
+
+    #[test]
+    fn test_may_contain_prefix_with_fixed_length() {
+        let filter = prefix_filter(PrefixExtractor::FixedLength(8));
+        assert!(filter.may_contain_prefix(b"user:007"));
+        // longer prefixes are checked by their first 8 bytes.
+        assert!(filter.may_contain_prefix(b"user:007:9"));
+        assert!(!filter.may_contain_prefix(b"user:070"));
+        // shorter prefixes can not be checked.
+        assert!(filter.may_contain_prefix(b"order"));
+
+        let whole_keys = filter_with_keys(0..100);
+        assert!(whole_keys.may_contain_prefix(b"anything"));
+    }
+
+    #[test]
+    fn test_prefix_extractor_survives_encoding() {
+        for extractor in [
+            PrefixExtractor::Delimiter(b':'),
+            PrefixExtractor::FixedLength(8),
+        ] {
+            let filter = prefix_filter(extractor);
+            let encoded = filter.encode();
+            let decoded = SlatedbBloomFilter::decode(&encoded).unwrap();
+            assert_eq!(decoded.prefix_extractor(), Some(extractor));
+            assert_eq!(decoded.encode(), encoded);
+
+            let view = BloomFilterView::new(&encoded).unwrap();
+            assert_eq!(view.prefix_extractor(), Some(extractor));
+            for user in 0..100 {
+                let prefix = format!("user:{:03}:", user);
+                assert_eq!(
+                    view.may_contain_prefix(prefix.as_bytes()),
+                    filter.may_contain_prefix(prefix.as_bytes())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_combine_filters_with_different_prefix_extractors() {
+        let delimited = prefix_filter(PrefixExtractor::Delimiter(b':'));
+        let fixed = prefix_filter(PrefixExtractor::FixedLength(8));
+        assert_eq!(
+            delimited.union(&fixed).err(),
+            Some(IncompatibleFilterError::PrefixExtractor {
+                left: Some(PrefixExtractor::Delimiter(b':')),
+                right: Some(PrefixExtractor::FixedLength(8)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_never_panics_on_damaged_input() {
+        let encoded = encoded_filter();
+        for len in 0..encoded.len() {
+            assert!(SlatedbBloomFilter::decode(&encoded[..len]).is_err());
+        }
+        for byte in 0..encoded.len() {
+            for bit in 0..8 {
+                let mut damaged = encoded.to_vec();
+                damaged[byte] ^= 1 << bit;
+                assert!(SlatedbBloomFilter::decode(&damaged).is_err());
+            }
+        }
+        // a huge num_bits must not overflow the length arithmetic.
+        let mut huge = encoded.to_vec();
+        huge[14..22].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(SlatedbBloomFilter::decode(&huge).is_err());
+    }
+
+    #[test]
+    fn test_view_answers_like_filter() {
+        let mut filter = SlatedbBloomFilter::new_with_seed(1003, 4, 7);
+        for i in 0..100 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        let encoded: Bytes = filter.encode();
+
+        let view = BloomFilterView::new(&encoded).unwrap();
+        assert_eq!(view.num_bits(), 1003);
+        assert_eq!(view.num_probes(), 4);
+        for i in 0..100 {
+            assert!(view.has_key(format!("key-{}", i).as_bytes()));
+        }
+        for i in 0..1000 {
+            let absent = format!("absent-{}", i);
+            assert_eq!(
+                view.has_key(absent.as_bytes()),
+                filter.has_key(absent.as_bytes())
+            );
+        }
+        // the view borrows the bits in place, right after the header.
+        assert_eq!(view.buffer.as_ptr(), encoded[HEADER_LEN..].as_ptr());
+        assert_eq!(view.to_filter().encode(), encoded);
+    }
+
+    #[test]
+    fn test_batch_matches_single_key_calls() {
+        // 37 keys is not a multiple of the batch size or of 64.
+        let keys: Vec<String> = (0..37).map(|i| format!("key-{}", i)).collect();
+        let mut batched = SlatedbBloomFilter::new_with_seed(1003, 4, 7)
+            .with_prefix_extractor(PrefixExtractor::FixedLength(5));
+        let mut single = batched.clone();
+        batched.add_keys(&keys);
+        for key in &keys {
+            single.add_key(key.as_bytes());
+        }
+        assert_eq!(batched.encode(), single.encode());
+
+        let lookups: Vec<String> = (0..200).map(|i| format!("key-{}", i * 3)).collect();
+        let bitmap = batched.has_keys(&lookups);
+        assert_eq!(bitmap.len(), 4);
+        for (i, key) in lookups.iter().enumerate() {
+            assert_eq!(
+                bitmap[i / 64] & (1 << (i % 64)) != 0,
+                batched.has_key(key.as_bytes()),
+                "{}",
+                key
+            );
+        }
+        assert!(bitmap[3] >> 8 == 0, "bits past the last key are set");
+        let empty: [&[u8]; 0] = [];
+        assert!(batched.has_keys(&empty).is_empty());
+    }
+
+    #[test]
+    fn test_hash_key_golden_vectors() {
+        let filter = SlatedbBloomFilter::new(1000, 3);
+        assert_eq!(
+            filter.hash_key(b"apple").collect::<Vec<_>>(),
+            vec![543, 392, 241]
+        );
+        assert_eq!(
+            filter.hash_key(b"banana").collect::<Vec<_>>(),
+            vec![90, 75, 60]
+        );
+        let seeded = SlatedbBloomFilter::new_with_seed(1000, 3, 42);
+        assert_eq!(
+            seeded.hash_key(b"apple").collect::<Vec<_>>(),
+            vec![159, 576, 993]
+        );
+    }
+
+    #[test]
+    fn test_size_with_bits_per_key() {
+        let size = BloomFilterSize::with_bits_per_key(1000, 10);
+        assert_eq!(size.num_bits, 10_000);
+        assert_eq!(size.num_probes, 6);
+        assert_eq!(size.bits_per_key(), 10.0);
+        // (1 - e^(-0.6))^6
+        assert!((size.false_positive_rate() - 0.0084).abs() < 0.0001);
+
+        let size = BloomFilterSize::with_bits_per_key(1000, 1);
+        assert_eq!(size.num_probes, 1);
+    }
+
+    #[test]
+    fn test_size_with_false_positive_rate() {
+        for fpr in [0.1, 0.01, 0.001, 0.0001] {
+            let size = BloomFilterSize::with_false_positive_rate(10_000, fpr);
+            assert!(size.false_positive_rate() <= fpr);
+        }
+        let size = BloomFilterSize::with_false_positive_rate(10_000, 0.01);
+        assert_eq!(size.num_bits, 100_000);
+        assert_eq!(size.num_probes, 6);
+    }
+
+    #[test]
+    fn test_sized_filter_has_no_false_negatives() {
+        let size = BloomFilterSize::with_false_positive_rate(500, 0.01);
+        let mut filter = SlatedbBloomFilter::with_size(size);
+        for i in 0..500 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        for i in 0..500 {
+            assert!(filter.has_key(format!("key-{}", i).as_bytes()));
+        }
+        assert_eq!(filter.false_positive_rate(500), size.false_positive_rate());
+    }
+
+    #[test]
+    fn test_observed_false_positive_rate_matches_theory() {
+        for bits_per_key in [4, 8, 12] {
+            let size = BloomFilterSize::with_bits_per_key(10_000, bits_per_key);
+            let mut filter = SlatedbBloomFilter::with_size(size);
+            for i in 0..10_000 {
+                filter.add_key(format!("key-{}", i).as_bytes());
+            }
+            let false_positives = (0..100_000)
+                .filter(|i| filter.has_key(format!("absent-{}", i).as_bytes()))
+                .count();
+            let observed = false_positives as f64 / 100_000.0;
+            let theoretical = size.false_positive_rate();
+            assert!(
+                (observed - theoretical).abs() < 0.2 * theoretical,
+                "{} bits/key: observed {}, theoretical {}",
+                bits_per_key,
+                observed,
+                theoretical
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimated_len() {
+        let mut filter =
+            SlatedbBloomFilter::with_size(BloomFilterSize::with_bits_per_key(1000, 10));
+        assert_eq!(filter.estimated_len(), 0);
+        for i in 0..1000 {
+            filter.insert(format!("key-{}", i));
+        }
+        let estimate = filter.estimated_len();
+        assert!((950..=1050).contains(&estimate), "estimate {}", estimate);
+    }
+
+    fn filter_with_keys(keys: std::ops::Range<usize>) -> SlatedbBloomFilter {
+        let mut filter = SlatedbBloomFilter::with_size(BloomFilterSize::with_bits_per_key(200, 10));
+        for i in keys {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        filter
+    }
+
+    #[test]
+    fn test_union() {
+        let left = filter_with_keys(0..100);
+        let right = filter_with_keys(100..200);
+        let union = left.union(&right).unwrap();
+        for i in 0..200 {
+            assert!(union.has_key(format!("key-{}", i).as_bytes()));
+        }
+        // same bits as a filter built from all keys.
+        assert_eq!(union.encode(), filter_with_keys(0..200).encode());
+
+        let mut in_place = left.clone();
+        in_place |= &right;
+        assert_eq!(in_place.encode(), union.encode());
+    }
+
+    #[test]
+    fn test_intersect() {
+        let left = filter_with_keys(0..150);
+        let right = filter_with_keys(50..200);
+        let intersection = left.intersect(&right).unwrap();
+        for i in 50..150 {
+            assert!(intersection.has_key(format!("key-{}", i).as_bytes()));
+        }
+        let only_one_side = (0..50)
+            .chain(150..200)
+            .filter(|i| intersection.has_key(format!("key-{}", i).as_bytes()))
+            .count();
+        assert!(only_one_side < 10, "{} keys leaked", only_one_side);
+
+        let mut in_place = left.clone();
+        in_place &= &right;
+        assert_eq!(in_place.encode(), intersection.encode());
+    }
+
+    #[test]
+    fn test_combine_incompatible_filters() {
+        let filter = SlatedbBloomFilter::new_with_seed(1000, 3, 1);
+        assert_eq!(
+            filter
+                .union(&SlatedbBloomFilter::new_with_seed(1001, 3, 1))
+                .err(),
+            Some(IncompatibleFilterError::NumBits {
+                left: 1000,
+                right: 1001
+            })
+        );
+        assert_eq!(
+            filter
+                .intersect(&SlatedbBloomFilter::new_with_seed(1000, 4, 1))
+                .err(),
+            Some(IncompatibleFilterError::NumProbes { left: 3, right: 4 })
+        );
+        assert_eq!(
+            filter
+                .union(&SlatedbBloomFilter::new_with_seed(1000, 3, 2))
+                .err(),
+            Some(IncompatibleFilterError::Seed { left: 1, right: 2 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "can not union bloom filters")]
+    fn test_union_assign_panics_on_incompatible_filters() {
+        let mut filter = SlatedbBloomFilter::new(1000, 3);
+        filter |= &SlatedbBloomFilter::new(2000, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "size must be positive")]
+    fn test_zero_size_panics() {
+        SlatedbBloomFilter::new(0, 3);
+    }
+
+    #[test]
+    fn test_fill_statistics() {
+        let size = BloomFilterSize::with_bits_per_key(10_000, 10);
+        let mut filter = SlatedbBloomFilter::with_size(size);
+        assert_eq!(filter.estimated_num_keys(), 0);
+        assert_eq!(filter.current_false_positive_rate(), 0.0);
+        for i in 0..10_000 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+
+        // 1 - e^(-6 / 10) of the bits are expected to be set.
+        assert!((filter.fill_ratio() - 0.451).abs() < 0.01);
+        let estimate = filter.estimated_num_keys();
+        assert!(
+            (9_800..=10_200).contains(&estimate),
+            "estimate {}",
+            estimate
+        );
+        let fpr = filter.current_false_positive_rate();
+        assert!(
+            (fpr - size.false_positive_rate()).abs() < 0.001,
+            "fpr {}",
+            fpr
+        );
+
+        // the statistics only depend on the bits, so they survive encoding.
+        let decoded = SlatedbBloomFilter::decode(&filter.encode()).unwrap();
+        assert_eq!(decoded.estimated_num_keys(), estimate);
+    }
+
+    #[test]
+    fn test_saturated_filter_estimate() {
+        let mut filter = SlatedbBloomFilter::new(64, 3);
+        for i in 0..1000 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        assert_eq!(filter.fill_ratio(), 1.0);
+        assert_eq!(filter.estimated_num_keys(), usize::MAX);
+        assert_eq!(filter.current_false_positive_rate(), 1.0);
+    }
+
+    #[test]
+    fn test_fill_ratio() {
+        let mut filter = SlatedbBloomFilter::new(1000, 3);
+        assert_eq!(filter.fill_ratio(), 0.0);
+        filter.add_key(b"apple");
+        assert_eq!(filter.fill_ratio(), 0.003);
+    }
+
     #[test]
     fn test_set_bits_does_not_unset_bits() {
         let mut buf = vec![0xFFu8; 3];
@@ -134,4 +1556,26 @@ mod tests {
             }
         }
     }
+
+    proptest! {
+        #[test]
+        fn prop_inserted_keys_are_always_found(
+            keys in vec(vec(any::<u8>(), 0..32), 1..200),
+            bits_per_key in 1u32..16,
+            seed in any::<u64>(),
+        ) {
+            let size = BloomFilterSize::with_bits_per_key(keys.len(), bits_per_key);
+            let mut filter =
+                SlatedbBloomFilter::new_with_seed(size.num_bits, size.num_probes, seed);
+            for key in &keys {
+                filter.add_key(key);
+            }
+            let encoded = filter.encode();
+            let view = BloomFilterView::new(&encoded).unwrap();
+            for key in &keys {
+                prop_assert!(filter.has_key(key));
+                prop_assert!(view.has_key(key));
+            }
+        }
+    }
 }
@@ -0,0 +1,287 @@
+use bytes::Bytes;
+
+use crate::filter::Filter;
+use crate::hash::{probes, splitmix64, DEFAULT_SEED};
+use crate::nibbles::{Nibbles, MAX_NIBBLE};
+use crate::slatedb::{estimate_num_keys, SlatedbBloomFilter};
+
+/// Largest value a 4-bit cell can hold.
+const MAX_CELL: u8 = MAX_NIBBLE;
+/// Value the cells of a key are set to by `with_false_positive_rate`.
+const DEFAULT_MAX: u8 = 3;
+
+/// Stable bloom filter, forgets old keys so the false positive rate stays bounded on an
+/// unbounded stream.
+///
+/// Every cell is a small counter. Adding a key first decrements `num_decrements` cells from a
+/// random position, then sets the key's cells to `max`. A key is present while all its cells
+/// are non-zero, so it fades out as later adds decrement its cells, and the fraction of zero
+/// cells converges to a fixed point instead of dropping to zero like in a plain bloom filter
+/// that keeps taking keys. The price is false negatives for keys that have not been seen for
+/// a while, which for duplicate detection means letting an old duplicate through.
+/// https://webdocs.cs.ualberta.ca/~drafiei/papers/DupDet06Sigmod.pdf
+pub struct StableBloomFilter {
+    cells: Nibbles,
+    num_probes: u32,
+    /// Value the cells of an added key are set to.
+    max: u8,
+    /// Cells decremented on every add.
+    num_decrements: usize,
+    seed: u64,
+    /// splitmix64 state picking where decrements start.
+    rng: u64,
+}
+
+impl StableBloomFilter {
+    /// Panics unless `num_cells` is positive, `max` is in `1..=15` and `num_decrements` is in
+    /// `1..=num_cells`.
+    pub fn new(num_cells: usize, num_probes: u32, max: u8, num_decrements: usize) -> Self {
+        Self::new_with_seed(num_cells, num_probes, max, num_decrements, DEFAULT_SEED)
+    }
+
+    pub fn new_with_seed(
+        num_cells: usize,
+        num_probes: u32,
+        max: u8,
+        num_decrements: usize,
+        seed: u64,
+    ) -> Self {
+        assert!(num_cells > 0, "num_cells must be positive");
+        assert!(
+            (1..=MAX_CELL).contains(&max),
+            "max must be in 1..={}, got {}",
+            MAX_CELL,
+            max
+        );
+        assert!(
+            (1..=num_cells).contains(&num_decrements),
+            "num_decrements must be in 1..={}, got {}",
+            num_cells,
+            num_decrements
+        );
+        Self {
+            cells: Nibbles::new(num_cells),
+            num_probes,
+            max,
+            num_decrements,
+            seed,
+            rng: seed,
+        }
+    }
+
+    /// Size a filter of `num_cells` cells whose false positive rate settles at or just below
+    /// `fpr`.
+    ///
+    /// Probes as many cells as a bloom filter would for `fpr`, and decrements just enough
+    /// cells per add to hold the zero cells at the fraction that gives that rate. More cells
+    /// remember more recent keys at the same rate. Panics unless `num_cells` is positive.
+    pub fn with_false_positive_rate(num_cells: usize, fpr: f64) -> Self {
+        assert!(num_cells > 0, "num_cells must be positive");
+        assert!(fpr > 0.0 && fpr < 1.0, "fpr must be in (0, 1), got {}", fpr);
+        let num_probes = (-fpr.log2()).ceil().max(1.0) as u32;
+        // invert stable_false_positive_rate for the number of decrements.
+        let zero_fraction = 1.0 - fpr.powf(1.0 / num_probes as f64);
+        let denominator = (1.0 / zero_fraction).powf(1.0 / DEFAULT_MAX as f64) - 1.0;
+        let per_probe = 1.0 / num_probes as f64 - 1.0 / num_cells as f64;
+        let num_decrements = (1.0 / (denominator * per_probe)).ceil() as usize;
+        Self::new(
+            num_cells,
+            num_probes,
+            DEFAULT_MAX,
+            num_decrements.clamp(1, num_cells),
+        )
+    }
+
+    pub fn num_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn num_probes(&self) -> u32 {
+        self.num_probes
+    }
+
+    pub fn num_decrements(&self) -> usize {
+        self.num_decrements
+    }
+
+    /// Number of bytes used by the filter, including the cells.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.cells.capacity_bytes()
+    }
+
+    pub fn add_key(&mut self, key: &[u8]) {
+        self.decrement();
+        for index in probes(key, self.seed, self.num_probes, self.cells.len()) {
+            self.cells.set(index, self.max);
+        }
+    }
+
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        probes(key, self.seed, self.num_probes, self.cells.len())
+            .all(|index| self.cells.get(index) > 0)
+    }
+
+    /// Add `key` and return whether it was already present, true for a probable duplicate.
+    pub fn check_and_add(&mut self, key: &[u8]) -> bool {
+        let present = self.has_key(key);
+        self.add_key(key);
+        present
+    }
+
+    /// Fraction of cells that are non-zero.
+    pub fn fill_ratio(&self) -> f64 {
+        self.non_zero_cells() as f64 / self.cells.len() as f64
+    }
+
+    /// False positive rate the filter converges to once it has taken enough keys.
+    ///
+    /// Deng & Rafiei: the fraction of zero cells settles at
+    /// `(1 / (1 + 1 / (p * (1 / k - 1 / m))))^max` for `p` decrements, `k` probes and `m`
+    /// cells, and a key is a false positive when all `k` of its cells are non-zero.
+    pub fn stable_false_positive_rate(&self) -> f64 {
+        let k = self.num_probes as f64;
+        let per_probe = 1.0 / k - 1.0 / self.cells.len() as f64;
+        let zero_fraction =
+            (1.0 / (1.0 + 1.0 / (self.num_decrements as f64 * per_probe))).powi(self.max as i32);
+        (1.0 - zero_fraction).powf(k)
+    }
+
+    /// Snapshot as a plain bloom filter holding the keys currently present, e.g. for
+    /// `encode`.
+    pub fn to_bloom_filter(&self) -> SlatedbBloomFilter {
+        self.cells.to_bloom_filter(self.num_probes, self.seed)
+    }
+
+    /// Decrement `num_decrements` consecutive cells from a random one, wrapping around.
+    fn decrement(&mut self) {
+        let num_cells = self.cells.len();
+        let start = (splitmix64(&mut self.rng) % num_cells as u64) as usize;
+        for offset in 0..self.num_decrements {
+            let index = (start + offset) % num_cells;
+            let value = self.cells.get(index);
+            if value > 0 {
+                self.cells.set(index, value - 1);
+            }
+        }
+    }
+
+    fn non_zero_cells(&self) -> usize {
+        self.cells.iter().filter(|value| *value > 0).count()
+    }
+}
+
+impl Filter for StableBloomFilter {
+    fn insert<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.add_key(key.as_ref())
+    }
+
+    fn may_contain<K: AsRef<[u8]>>(&self, key: K) -> bool {
+        self.has_key(key.as_ref())
+    }
+
+    /// Number of keys a bloom filter would hold with as many cells set, roughly the recent
+    /// keys the filter still remembers.
+    fn estimated_len(&self) -> usize {
+        estimate_num_keys(self.cells.len(), self.non_zero_cells(), self.num_probes)
+    }
+
+    /// Encode a snapshot as a plain bloom filter, the cell values are lost.
+    fn encode(&self) -> Bytes {
+        self.to_bloom_filter().encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple::SimpleBloomFilter;
+
+    #[test]
+    fn test_recent_keys_are_present() {
+        let mut filter = StableBloomFilter::new(1000, 3, 3, 10);
+        assert!(!filter.check_and_add(b"apple"));
+        assert!(filter.check_and_add(b"apple"));
+        for i in 0..20 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        // 20 adds decrement 200 of 1000 cells, not enough to clear a cell set to 3 three times.
+        assert!(filter.has_key(b"apple"));
+        assert!(!filter.has_key(b"grape"));
+    }
+
+    #[test]
+    fn test_old_keys_are_forgotten() {
+        let mut filter = StableBloomFilter::new(1000, 3, 1, 10);
+        filter.add_key(b"apple");
+        for i in 0..1000 {
+            filter.add_key(format!("key-{}", i).as_bytes());
+        }
+        // every cell was decremented ~10 times since apple was added.
+        assert!(!filter.has_key(b"apple"));
+    }
+
+    #[test]
+    fn test_with_false_positive_rate() {
+        let filter = StableBloomFilter::with_false_positive_rate(100_000, 0.01);
+        assert_eq!(filter.num_probes(), 7);
+        let stable = filter.stable_false_positive_rate();
+        assert!(stable <= 0.01 && stable > 0.008, "{}", stable);
+    }
+
+    #[test]
+    #[should_panic(expected = "num_cells must be positive")]
+    fn test_zero_cells_panics() {
+        StableBloomFilter::with_false_positive_rate(0, 0.01);
+    }
+
+    /// Stream distinct keys through a stable and a plain filter of the same size, measuring
+    /// the false positive rate after every window on keys that never occur.
+    #[test]
+    fn test_false_positive_rate_converges() {
+        const NUM_CELLS: usize = 20_000;
+        const WINDOW: usize = 2_000;
+        const NUM_ABSENT: usize = 10_000;
+        let mut stable = StableBloomFilter::with_false_positive_rate(NUM_CELLS, 0.02);
+        let mut plain = SimpleBloomFilter::new(NUM_CELLS, stable.num_probes());
+        let expected = stable.stable_false_positive_rate();
+
+        let mut next_key = 0;
+        let mut stable_fprs = Vec::new();
+        let mut plain_fprs = Vec::new();
+        for window in 0..30 {
+            for _ in 0..WINDOW {
+                let key = format!("key-{}", next_key);
+                stable.add_key(key.as_bytes());
+                plain.add_key(key.as_bytes());
+                next_key += 1;
+            }
+            let absent = |has_key: &dyn Fn(&[u8]) -> bool| {
+                let false_positives = (0..NUM_ABSENT)
+                    .filter(|i| has_key(format!("absent-{}-{}", window, i).as_bytes()))
+                    .count();
+                false_positives as f64 / NUM_ABSENT as f64
+            };
+            stable_fprs.push(absent(&|key| stable.has_key(key)));
+            plain_fprs.push(absent(&|key| plain.has_key(key)));
+        }
+
+        // the rate climbs while the filter fills, then holds around the fixed point.
+        assert!(stable_fprs[0] < expected * 0.75, "{:?}", stable_fprs);
+        for fpr in &stable_fprs[10..] {
+            assert!(
+                (fpr - expected).abs() < expected * 0.3,
+                "{} vs {}, {:?}",
+                fpr,
+                expected,
+                stable_fprs
+            );
+        }
+        // while a plain filter of the same size answers yes to almost everything.
+        assert!(plain_fprs[29] > 0.99, "{:?}", plain_fprs);
+        // the most recent keys are still remembered.
+        let remembered = (next_key - 100..next_key)
+            .filter(|i| stable.has_key(format!("key-{}", i).as_bytes()))
+            .count();
+        assert_eq!(remembered, 100);
+    }
+}